@@ -4,22 +4,176 @@
 
 for i in church-list.ls inverse.ls test.ls type-in-type.ls
 do
-	./target/debug/lofer-lang prelude.ls $i || exit
+	./target/debug/lofer-lang $i || exit
 done
 
 for i in negative_extras.ls ind-safety-theorem.ls inductive-church.ls
 do
-	./target/debug/lofer-lang prelude.ls negative.ls $i || exit
+	./target/debug/lofer-lang $i || exit
 done
 
-./target/debug/lofer-lang prelude.ls rec.ls mutual-rec.ls || exit
+./target/debug/lofer-lang mutual-rec.ls || exit
 
 for i in overload-test.ls fix-test.ls
 do
-	./target/debug/lofer-lang prelude.ls rec.ls data.ls nat.ls list.ls eq.ls $i || exit
+	./target/debug/lofer-lang $i || exit
 done
 
-./target/debug/lofer-lang prelude.ls rec.ls data.ls eq.ls weak.ls
+./target/debug/lofer-lang weak.ls
 
-./target/debug/lofer-lang prelude.ls rec.ls signed.ls data.ls nat.ls int.ls rat.ls eq.ls num-tests.ls
+./target/debug/lofer-lang num-tests.ls
 
+for i in data-types.ls patterns.ls termination.ls levels.ls holes.ls \
+	overloads.ls
+do
+	./target/debug/lofer-lang $i || exit
+done
+
+./target/debug/lofer-lang --eval answer --eval offset --eval greeting \
+	--eval "bigger 3 9" --eval stuck --eval too_big --eval "double 21" \
+	--eval "Int_abs (Int_negate -7)" primitives.ls || exit
+
+# every item in these has to be rejected
+for i in data-types-rejected.ls patterns-rejected.ls \
+	termination-rejected.ls primitives-rejected.ls levels-rejected.ls \
+	holes-rejected.ls overloads-rejected.ls
+do
+	if ./target/debug/lofer-lang --keep-going $i
+	then
+		exit 1
+	fi
+done
+
+# flags apply to every file, even ones given before them
+./target/debug/lofer-lang cumulative.ls --cumulative || exit
+if ./target/debug/lofer-lang --keep-going cumulative.ls
+then
+	exit 1
+fi
+if ./target/debug/lofer-lang --cumulative --keep-going cumulative-rejected.ls
+then
+	exit 1
+fi
+
+# type-in-type.ls postulates the collapse, so it checks with or without it
+for i in type-in-type.ls collapse.ls
+do
+	./target/debug/lofer-lang --type-in-type $i || exit
+done
+if ./target/debug/lofer-lang --keep-going collapse.ls
+then
+	exit 1
+fi
+if ./target/debug/lofer-lang --type-in-type --keep-going collapse-rejected.ls
+then
+	exit 1
+fi
+
+# a second run with the same cache has to print exactly what the first did
+cache=$(mktemp -d) || exit
+for i in 1 2
+do
+	./target/debug/lofer-lang --cache "$cache" data-types.ls \
+		> "$cache/out$i" 2>&1 || exit
+	if ./target/debug/lofer-lang --cache "$cache" --keep-going \
+		data-types-rejected.ls > "$cache/rejected$i" 2>&1
+	then
+		exit 1
+	fi
+done
+cmp "$cache/out1" "$cache/out2" || exit
+cmp "$cache/rejected1" "$cache/rejected2" || exit
+cat "$cache/out2" "$cache/rejected2"
+rm -r "$cache"
+
+printf '7\nworld\n' | ./target/debug/lofer-lang --run run.ls || exit
+
+# these fail while running instead
+if printf 'seven\n' | ./target/debug/lofer-lang --run run.ls
+then
+	exit 1
+fi
+if ./target/debug/lofer-lang --run run-rejected.ls
+then
+	exit 1
+fi
+
+# frames each message the way the language server protocol expects
+lsp_send() {
+	for msg
+	do
+		printf 'Content-Length: %d\r\n\r\n%s' ${#msg} "$msg"
+	done
+}
+
+lsp_message() {
+	printf '{"jsonrpc":"2.0",%s}' "$1"
+}
+
+# the contents of a file as a JSON string
+json_text() {
+	sed 's/\\/\\\\/g; s/"/\\"/g' "$1" | awk '{ printf "%s\\n", $0 }'
+}
+
+lsp_open() {
+	lsp_message "$(printf '"method":"textDocument/didOpen","params":
+		{"textDocument":{"uri":"file://%s/%s","languageId":"lofer",
+		"version":1,"text":"%s"}}' "$PWD" "$1" "$(json_text "$1")")"
+}
+
+lsp_at() {
+	lsp_message "$(printf '"id":%d,"method":"textDocument/%s","params":
+		{"textDocument":{"uri":"file://%s/%s"},
+		"position":{"line":%d,"character":%d}}' \
+		"$1" "$2" "$PWD" "$3" "$4" "$5")"
+}
+
+# hover over and go to the definition of half where it calls itself, then
+# open a file with errors, which should publish a diagnostic for each
+lsp=$(lsp_send \
+	"$(lsp_message '"id":1,"method":"initialize","params":
+		{"capabilities":{}}')" \
+	"$(lsp_message '"method":"initialized","params":{}')" \
+	"$(lsp_open patterns.ls)" \
+	"$(lsp_at 2 hover patterns.ls 29 27)" \
+	"$(lsp_at 3 definition patterns.ls 29 27)" \
+	"$(lsp_open patterns-rejected.ls)" \
+	"$(lsp_message '"id":4,"method":"shutdown"')" \
+	"$(lsp_message '"method":"exit"')" |
+	./target/debug/lofer-lsp | tr -d '\r' |
+	sed "s|$PWD|.|g; s/}Content-Length: [0-9]*$/}/; /^Content-Length/d; /^$/d"
+) || exit
+printf '%s\n' "$lsp"
+for diagnostics in '\[\],"uri":"file://./patterns.ls"' \
+	'\[{.*"uri":"file://./patterns-rejected.ls"'
+do
+	printf '%s\n' "$lsp" | grep -q "\"diagnostics\":$diagnostics" || exit
+done
+
+# entries typed into the repl that check, and then ones that don't, which
+# must each be reported without ending the session
+errors=$(mktemp) || exit
+printf '%s\n' ':type map half' ':eval half (suc (suc (suc (suc zero))))' \
+	'double: Nat -> Nat' 'double = Nat_elim (\n -> Nat) zero \' \
+	'  (\n r -> suc (suc r))' ':eval double (half (suc (suc zero)))' \
+	':print pred' ':print half' ':print double' ':overloads and' ':quit' |
+	./target/debug/lofer-lang --repl patterns.ls 2> "$errors" || exit
+echo
+cat "$errors"
+if [ -s "$errors" ]
+then
+	exit 1
+fi
+printf '%s\n' ':eval missing' ':type suc true' 'bad: Nat' 'bad = true' \
+	':eval bad' ':print nowhere' ':overloads nowhere' ':frobnicate' \
+	':reload' ':type zero' |
+	./target/debug/lofer-lang --repl patterns-rejected.ls 2> "$errors" ||
+	exit
+echo
+cat "$errors"
+if [ "$(grep -c -e '^<repl>' -e '^<eval>' -e '^nothing' -e '^unknown' \
+	"$errors")" -ne 7 ]
+then
+	exit 1
+fi
+rm "$errors"
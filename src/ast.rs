@@ -1,40 +1,148 @@
+// start and end are byte offsets into the whole file, line and column
+// (both 1-based) are the position of start
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
 #[derive(Clone)]
 pub enum Expr {
     Arrow(ArrowExpr),
     Alg(AlgExpr),
+    Lambda(LambdaExpr),
+    Literal(LiteralExpr),
+    Hole(HoleExpr),
+}
+
+impl Expr {
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Arrow(arrow) => arrow.span,
+            Expr::Alg(alg) => alg.span,
+            Expr::Lambda(lambda) => lambda.span,
+            Expr::Literal(literal) => literal.span,
+            Expr::Hole(hole) => hole.span,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Param {
+    pub name: Option<String>,
+    pub typ: Expr,
+    pub implicit: bool,
 }
 
 #[derive(Clone)]
 pub struct ArrowExpr {
-    pub params: Vec<(Option<String>, Expr)>,
+    pub params: Vec<Param>,
     pub output: Box<Expr>,
+    pub span: Span,
 }
 
 #[derive(Clone)]
 pub struct AlgExpr {
     pub head: String,
     pub tail: Vec<Expr>,
+    pub span: Span,
+}
+
+#[derive(Clone)]
+pub struct LambdaExpr {
+    pub params: Vec<String>,
+    pub body: Box<Expr>,
+    pub span: Span,
+}
+
+// 12 is a Nat, +12 and -12 are Ints, and "twelve" is a String
+#[derive(Clone)]
+pub enum Literal {
+    Nat(u64),
+    Int(i64),
+    Str(String),
+}
+
+#[derive(Clone)]
+pub struct LiteralExpr {
+    pub value: Literal,
+    pub span: Span,
+}
+
+// names only tell holes apart when they are reported
+#[derive(Clone)]
+pub struct HoleExpr {
+    pub name: Option<String>,
+    pub span: Span,
+}
+
+// a variable, or a constructor applied to more patterns
+// which one a lone name is depends on whether it names a constructor
+#[derive(Clone)]
+pub struct Pattern {
+    pub name: String,
+    pub args: Vec<Pattern>,
+    pub span: Span,
 }
 
 pub struct Function {
     pub fname: String,
-    pub vars: Vec<String>,
+    pub patterns: Vec<Pattern>,
     pub body: Expr,
+    pub span: Span,
 }
 
 pub struct Annotation {
     pub is_post: bool,
+    // checked, but allowed to recurse without terminating
+    pub is_partial: bool,
     pub name: String,
     pub typ: Expr,
+    pub span: Span,
+}
+
+// data Name (param: A) ..: Type where constructor: T; ..
+// constructors can also be given as annotations on the lines indented under it
+pub struct Data {
+    pub name: String,
+    pub params: Vec<Param>,
+    pub typ: Expr,
+    pub constructors: Vec<Annotation>,
+    pub span: Span,
+}
+
+// the items indented under a `mutual` line, which can all refer to each other
+pub struct Mutual {
+    pub items: Vec<Item>,
+    pub span: Span,
+}
+
+// import name, which checks name.ls first if it hasn't been already
+pub struct Import {
+    pub module: String,
+    pub span: Span,
 }
 
 pub enum Line {
     Annotation(Annotation),
     Function(Function),
+    Data(Data),
+    Mutual(Span),
+    Import(Import),
 }
 
+// either a data declaration, a mutual block, an import, or an annotation
+// and/or definition
 pub struct Item {
+    pub file: String,
     pub annotation: Option<Annotation>,
     pub definition: Option<Function>,
+    // any further clauses of a pattern matching definition
+    pub clauses: Vec<Function>,
     pub associated: Vec<Item>,
+    pub data: Option<Data>,
+    pub mutual: Option<Mutual>,
+    pub import: Option<Import>,
 }
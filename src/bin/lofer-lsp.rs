@@ -0,0 +1,512 @@
+// a language server speaking LSP over stdio, which checks each open file
+// whenever it changes, along with everything that it imports
+
+extern crate lofer_lang;
+extern crate lsp_server;
+extern crate lsp_types;
+extern crate serde_json;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+
+use lofer_lang::{Globals, LoferError, Location, Modules, ProgramParser};
+use lofer_lang::ast;
+use lsp_server::{Connection, Message, Notification, Request, Response};
+use lsp_types::notification::{self, Notification as _};
+use lsp_types::request::{self, Request as _};
+use lsp_types::{
+    Diagnostic, DiagnosticSeverity, DocumentSymbol, DocumentSymbolResponse,
+    GotoDefinitionResponse, Hover, HoverContents, HoverProviderCapability,
+    MarkupContent, MarkupKind, OneOf, Position, PublishDiagnosticsParams,
+    Range, ServerCapabilities, SymbolKind, TextDocumentSyncCapability,
+    TextDocumentSyncKind, Url,
+};
+
+type Result<T> = ::std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+// what the last check of an open file found
+struct Document {
+    text: String,
+    globals: Globals,
+    // every file that got diagnostics from checking this one, so that they
+    // can be cleared once they're fixed
+    published: Vec<Url>,
+}
+
+struct Server {
+    parser: ProgramParser,
+    // the flags given to the server, which every check uses
+    search_path: Vec<String>,
+    cumulative: bool,
+    type_in_type: bool,
+    documents: HashMap<Url, Document>,
+}
+
+fn main() {
+    let mut args = ::std::env::args();
+    args.next();  // first argument is executable itself
+
+    let mut server = Server {
+        parser: ProgramParser::new(),
+        search_path: Vec::new(),
+        cumulative: false,
+        type_in_type: false,
+        documents: HashMap::new(),
+    };
+    while let Some(arg) = args.next() {
+        if arg == "--cumulative" {
+            server.cumulative = true;
+        } else if arg == "--type-in-type" {
+            server.type_in_type = true;
+        } else if arg == "--path" || arg == "-I" {
+            match args.next() {
+                Some(dir) => server.search_path.push(dir),
+                None => {
+                    eprintln!("{} needs a directory to search", arg);
+                    ::std::process::exit(1);
+                },
+            }
+        } else {
+            eprintln!("unknown argument {}", arg);
+            ::std::process::exit(1);
+        }
+    }
+
+    if let Err(e) = serve(&mut server) {
+        eprintln!("{}", e);
+        ::std::process::exit(1);
+    }
+}
+
+fn serve(server: &mut Server) -> Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(
+            TextDocumentSyncKind::FULL)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        ..ServerCapabilities::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+
+    for message in &connection.receiver {
+        match message {
+            Message::Request(req) => {
+                if connection.handle_shutdown(&req)? {
+                    break;
+                }
+                let response = server.respond(req);
+                connection.sender.send(Message::Response(response))?;
+            },
+            Message::Notification(note) => {
+                for note in server.notify(note)? {
+                    connection.sender.send(Message::Notification(note))?;
+                }
+            },
+            Message::Response(_) => (),
+        }
+    }
+    // the writer thread only stops once nothing can send to it
+    drop(connection);
+    io_threads.join()?;
+    Ok(())
+}
+
+impl Server {
+    // handles a change to the open files, returning the diagnostics to
+    // publish because of it
+    fn notify(&mut self, note: Notification)
+        -> Result<Vec<Notification>>
+    {
+        Ok(match &note.method[..] {
+            notification::DidOpenTextDocument::METHOD => {
+                let params: lsp_types::DidOpenTextDocumentParams =
+                    serde_json::from_value(note.params)?;
+                let doc = params.text_document;
+                self.check(doc.uri, doc.text)
+            },
+            notification::DidChangeTextDocument::METHOD => {
+                let params: lsp_types::DidChangeTextDocumentParams =
+                    serde_json::from_value(note.params)?;
+                // with full syncing, the last change is the whole text
+                match params.content_changes.into_iter().last() {
+                    Some(change) => {
+                        self.check(params.text_document.uri, change.text)
+                    },
+                    None => Vec::new(),
+                }
+            },
+            notification::DidCloseTextDocument::METHOD => {
+                let params: lsp_types::DidCloseTextDocumentParams =
+                    serde_json::from_value(note.params)?;
+                match self.documents.remove(&params.text_document.uri) {
+                    Some(doc) => doc.published.into_iter()
+                        .map(|uri| publish(uri, Vec::new()))
+                        .collect(),
+                    None => Vec::new(),
+                }
+            },
+            _ => Vec::new(),
+        })
+    }
+
+    fn respond(&self, req: Request) -> Response {
+        let id = req.id.clone();
+        let result = match &req.method[..] {
+            request::HoverRequest::METHOD => {
+                serde_json::from_value(req.params)
+                    .map(|params| self.hover(params))
+                    .and_then(serde_json::to_value)
+            },
+            request::GotoDefinition::METHOD => {
+                serde_json::from_value(req.params)
+                    .map(|params| self.definition(params))
+                    .and_then(serde_json::to_value)
+            },
+            request::DocumentSymbolRequest::METHOD => {
+                serde_json::from_value(req.params)
+                    .map(|params| self.symbols(params))
+                    .and_then(serde_json::to_value)
+            },
+            _ => return Response::new_err(
+                id,
+                lsp_server::ErrorCode::MethodNotFound as i32,
+                format!("unsupported request {}", req.method),
+            ),
+        };
+        match result {
+            Ok(result) => Response::new_ok(id, result),
+            Err(e) => Response::new_err(
+                id,
+                lsp_server::ErrorCode::InvalidParams as i32,
+                e.to_string(),
+            ),
+        }
+    }
+
+    // checks text as the file at uri, keeping going after errors so that
+    // as much as possible is known about the rest of it
+    fn check(&mut self, uri: Url, text: String) -> Vec<Notification> {
+        let path = match uri.to_file_path() {
+            Ok(path) => path.to_string_lossy().into_owned(),
+            Err(()) => uri.path().to_string(),
+        };
+        let mut globals = Globals::new();
+        globals.options_mut().cumulative = self.cumulative;
+        globals.options_mut().type_in_type = self.type_in_type;
+        let mut modules = Modules::new();
+        modules.set_quiet();
+        for dir in &self.search_path {
+            modules.add_search_dir(dir);
+        }
+        let errors = modules.load_source(
+            &self.parser, &mut globals, &path, &text, true);
+        // each hole gets a diagnostic of its own, where it is
+        let errors: Vec<_> = errors.into_iter()
+            .flat_map(|e| match e {
+                LoferError::Holes { goals, .. } => goals.into_iter()
+                    .map(|goal| LoferError::Holes {
+                        loc: goal.loc.clone(),
+                        goals: vec![goal],
+                    })
+                    .collect(),
+                e => vec![e],
+            })
+            .collect();
+
+        // errors in imported files are published to those files
+        let mut by_file: Vec<(Url, Vec<Diagnostic>)> = Vec::new();
+        for e in &errors {
+            let file = &e.location().file;
+            let file_uri = if *file == path {
+                uri.clone()
+            } else {
+                match Url::from_file_path(file) {
+                    Ok(file_uri) => file_uri,
+                    Err(()) => uri.clone(),
+                }
+            };
+            let diagnostic = if file_uri == uri {
+                diagnostic(&text, e)
+            } else {
+                let contents = fs::read_to_string(file).unwrap_or_default();
+                diagnostic(&contents, e)
+            };
+            match by_file.iter_mut().find(|(each, _)| *each == file_uri) {
+                Some((_, diagnostics)) => diagnostics.push(diagnostic),
+                None => by_file.push((file_uri, vec![diagnostic])),
+            }
+        }
+        if !by_file.iter().any(|(each, _)| *each == uri) {
+            by_file.push((uri.clone(), Vec::new()));
+        }
+
+        let mut notes = Vec::new();
+        if let Some(old) = self.documents.get(&uri) {
+            for stale in &old.published {
+                if !by_file.iter().any(|(each, _)| each == stale) {
+                    notes.push(publish(stale.clone(), Vec::new()));
+                }
+            }
+        }
+        let published = by_file.iter().map(|(each, _)| each.clone()).collect();
+        for (file_uri, diagnostics) in by_file {
+            notes.push(publish(file_uri, diagnostics));
+        }
+        self.documents.insert(uri, Document { text, globals, published });
+        notes
+    }
+
+    // the types of every global that the name under the cursor could be
+    fn hover(&self, params: lsp_types::HoverParams) -> Option<Hover> {
+        let at = params.text_document_position_params;
+        let doc = self.documents.get(&at.text_document.uri)?;
+        let (start, end) = word_at(&doc.text, offset(&doc.text, at.position))?;
+        let name = &doc.text[start..end];
+        let declarations = lofer_lang::declarations(&doc.globals, name);
+        if declarations.is_empty() {
+            return None;
+        }
+        let lines: Vec<_> = declarations.iter()
+            .map(|each| format!("{}: {}", each.name, each.ty))
+            .collect();
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: format!("```\n{}\n```", lines.join("\n")),
+            }),
+            range: Some(Range {
+                start: position(&doc.text, start),
+                end: position(&doc.text, end),
+            }),
+        })
+    }
+
+    // where every global that the name under the cursor could be is declared
+    fn definition(&self, params: lsp_types::GotoDefinitionParams)
+        -> Option<GotoDefinitionResponse>
+    {
+        let at = params.text_document_position_params;
+        let uri = &at.text_document.uri;
+        let doc = self.documents.get(uri)?;
+        let (start, end) = word_at(&doc.text, offset(&doc.text, at.position))?;
+        let name = &doc.text[start..end];
+        let mut locations = Vec::new();
+        for each in lofer_lang::declarations(&doc.globals, name) {
+            let loc = match each.loc {
+                Some(loc) => loc,
+                None => continue,
+            };
+            let target = match Url::from_file_path(&loc.file) {
+                Ok(target) => target,
+                Err(()) => uri.clone(),
+            };
+            let text = match self.documents.get(&target) {
+                Some(open) => open.text.clone(),
+                None => fs::read_to_string(&loc.file).unwrap_or_default(),
+            };
+            locations.push(lsp_types::Location {
+                uri: target,
+                range: range_at(&text, &loc),
+            });
+        }
+        if locations.is_empty() {
+            return None;
+        }
+        Some(GotoDefinitionResponse::Array(locations))
+    }
+
+    // an outline of the items in a file, with the types that they checked
+    fn symbols(&self, params: lsp_types::DocumentSymbolParams)
+        -> Option<DocumentSymbolResponse>
+    {
+        let doc = self.documents.get(&params.text_document.uri)?;
+        let (items, _) = self.parser.parse_recover("", &doc.text);
+        let symbols = items.iter()
+            .filter_map(|item| symbol(doc, item))
+            .collect();
+        Some(DocumentSymbolResponse::Nested(symbols))
+    }
+}
+
+fn publish(uri: Url, diagnostics: Vec<Diagnostic>) -> Notification {
+    let params = PublishDiagnosticsParams { uri, diagnostics, version: None };
+    Notification::new(
+        notification::PublishDiagnostics::METHOD.into(), params)
+}
+
+fn diagnostic(text: &str, e: &LoferError) -> Diagnostic {
+    // the location is already given by the range
+    let message = e.message();
+    // holes are questions rather than mistakes
+    let severity = match e {
+        LoferError::Holes { .. } => DiagnosticSeverity::INFORMATION,
+        _ => DiagnosticSeverity::ERROR,
+    };
+    Diagnostic {
+        range: range_at(text, e.location()),
+        severity: Some(severity),
+        source: Some("lofer".into()),
+        message,
+        ..Diagnostic::default()
+    }
+}
+
+#[allow(deprecated)]
+fn symbol(doc: &Document, item: &ast::Item) -> Option<DocumentSymbol> {
+    let (name, kind, span) = if let Some(data) = &item.data {
+        (data.name.clone(), SymbolKind::ENUM, data.span)
+    } else if let Some(mutual) = &item.mutual {
+        ("mutual".to_string(), SymbolKind::NAMESPACE, mutual.span)
+    } else if let Some(import) = &item.import {
+        (import.module.clone(), SymbolKind::MODULE, import.span)
+    } else if let Some(annotation) = &item.annotation {
+        (annotation.name.clone(), SymbolKind::FUNCTION, annotation.span)
+    } else {
+        let definition = item.definition.as_ref()?;
+        (definition.fname.clone(), SymbolKind::FUNCTION, definition.span)
+    };
+
+    // the whole item, from its annotation to the end of its last clause
+    let mut spans = vec![span];
+    spans.extend(item.definition.iter().map(|def| def.span));
+    spans.extend(item.clauses.iter().map(|def| def.span));
+    let mut children = Vec::new();
+    if let Some(data) = &item.data {
+        for constructor in &data.constructors {
+            spans.push(constructor.span);
+            children.push(DocumentSymbol {
+                name: constructor.name.clone(),
+                detail: checked_type(doc, &constructor.name, constructor.span),
+                kind: SymbolKind::ENUM_MEMBER,
+                tags: None,
+                deprecated: None,
+                range: span_range(&doc.text, constructor.span),
+                selection_range: span_range(&doc.text, constructor.span),
+                children: None,
+            });
+        }
+    }
+    let nested = item.mutual.iter()
+        .flat_map(|mutual| &mutual.items)
+        .chain(&item.associated);
+    for each in nested {
+        children.extend(symbol(doc, each));
+    }
+    let start = spans.iter().map(|span| span.start).min().unwrap();
+    let end = spans.iter().map(|span| span.end).max().unwrap();
+    let range = Range {
+        start: position(&doc.text, start),
+        end: position(&doc.text, end),
+    };
+
+    let detail = match kind {
+        SymbolKind::MODULE | SymbolKind::NAMESPACE => None,
+        _ => checked_type(doc, &name, span),
+    };
+    Some(DocumentSymbol {
+        name,
+        detail,
+        kind,
+        tags: None,
+        deprecated: None,
+        range,
+        selection_range: span_range(&doc.text, span),
+        children: if !children.is_empty() { Some(children) } else { None },
+    })
+}
+
+// the type of the global called name that was declared at span, if it
+// checked
+fn checked_type(doc: &Document, name: &str, span: ast::Span)
+    -> Option<String>
+{
+    lofer_lang::declarations(&doc.globals, name)
+        .into_iter()
+        .find(|each| each.loc.as_ref()
+            .is_some_and(|loc| loc.line == span.line
+                && loc.column == span.column))
+        .map(|each| each.ty)
+}
+
+// the byte offset of an LSP position, which counts UTF-16 code units
+fn offset(text: &str, at: Position) -> usize {
+    let line_start = text.split('\n')
+        .take(at.line as usize)
+        .map(|line| line.len() + 1)
+        .sum::<usize>()
+        .min(text.len());
+    let line = text[line_start..].split('\n').next().unwrap_or("");
+    let mut units = 0;
+    for (i, c) in line.char_indices() {
+        if units >= at.character as usize {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    line_start + line.len()
+}
+
+fn position(text: &str, offset: usize) -> Position {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Position {
+        line: before.matches('\n').count() as u32,
+        character: before[line_start..].encode_utf16().count() as u32,
+    }
+}
+
+fn span_range(text: &str, span: ast::Span) -> Range {
+    Range { start: position(text, span.start), end: position(text, span.end) }
+}
+
+// the name at loc, or just the character there if there isn't one
+// the columns of locations count bytes, which position turns into UTF-16
+// code units, so this only has to find the character the column is in
+fn range_at(text: &str, loc: &Location) -> Range {
+    let line_start = text.split('\n')
+        .take(loc.line.saturating_sub(1))
+        .map(|line| line.len() + 1)
+        .sum::<usize>()
+        .min(text.len());
+    let line = text[line_start..].split('\n').next().unwrap_or("");
+    let column = loc.column.saturating_sub(1);
+    let start = line_start + line.char_indices()
+        .map(|(i, _)| i)
+        .take_while(|&i| i <= column)
+        .last()
+        .unwrap_or(0);
+    let end = match word_at(text, start) {
+        Some((word_start, end)) if word_start == start => end,
+        _ => text[start..].chars().next().map_or(start, |c| {
+            start + c.len_utf8()
+        }),
+    };
+    Range { start: position(text, start), end: position(text, end) }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || "_'.".contains(c)
+}
+
+// the bounds of the name containing offset, or ending right before it
+fn word_at(text: &str, offset: usize) -> Option<(usize, usize)> {
+    if !text.is_char_boundary(offset) {
+        return None;
+    }
+    let start = text[..offset]
+        .char_indices()
+        .rev()
+        .find(|&(_, c)| !is_name_char(c))
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    let end = text[offset..]
+        .find(|c| !is_name_char(c))
+        .map_or(text.len(), |i| offset + i);
+    if start == end {
+        None
+    } else {
+        Some((start, end))
+    }
+}
@@ -0,0 +1,330 @@
+use std::rc::Rc;
+use std::str::FromStr;
+
+use prims;
+
+use error::Location;
+
+use super::{DataType, Def, Expr, Globals, Ident, Item, Options, Param};
+
+// bumped whenever the format below, or what checking produces, changes
+const FORMAT: &str = "lofer-cache 4";
+
+// FNV-1a, which unlike the std hashers is the same in every build
+struct Hasher(u64);
+
+impl Hasher {
+    fn new() -> Self {
+        Hasher(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= u64::from(b);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+        // separate consecutive writes, so "ab" "c" differs from "a" "bc"
+        self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+    }
+}
+
+// identifies the result of checking source, found at path, as the module
+// called module, after whatever was loaded before had left globals in the
+// state state
+// since every global is referred to by its position, that includes all of
+// the file's dependencies, and whatever else came before them
+pub fn key(
+    state: u64,
+    options: Options,
+    module: &str,
+    path: &str,
+    source: &str,
+) -> u64 {
+    let mut hasher = Hasher::new();
+    hasher.write(FORMAT.as_bytes());
+    hasher.write(env!("CARGO_PKG_VERSION").as_bytes());
+    hasher.write(&state.to_le_bytes());
+    hasher.write(&[options.cumulative as u8, options.type_in_type as u8]);
+    hasher.write(module.as_bytes());
+    hasher.write(path.as_bytes());
+    hasher.write(source.as_bytes());
+    hasher.0
+}
+
+// the globals from index from onwards, and the data types from data_from,
+// which have to have checked without errors
+pub fn save(globals: &Globals, from: usize, data_from: usize) -> String {
+    // associated helpers are the only globals without a short name
+    let mut short_names = vec![None; globals.names.len() - from];
+    for (short_name, overloads) in
+        globals.short_names.iter().zip(&globals.overloads)
+    {
+        for &i in overloads.iter().filter(|&&i| i >= from) {
+            short_names[i - from] = Some(short_name);
+        }
+    }
+    let mut out = Writer(FORMAT.to_string());
+    out.number(globals.names.len() - from);
+    for (i, short_name) in (from..globals.names.len()).zip(short_names) {
+        out.string(&globals.names[i]);
+        match short_name {
+            Some(short_name) => {
+                out.number(1);
+                out.string(short_name);
+            },
+            None => out.number(0),
+        }
+        match &globals.locations[i] {
+            Some(loc) => {
+                out.number(1);
+                out.string(&loc.file);
+                out.number(loc.line);
+                out.number(loc.column);
+            },
+            None => out.number(0),
+        }
+        let Item { ty, def } = &globals.defs[i];
+        out.expr(ty);
+        match def {
+            Some((params, def)) => {
+                out.number(1);
+                out.number(*params);
+                out.def(def);
+            },
+            None => out.number(0),
+        }
+    }
+    let data_types = &globals.data_types[data_from..];
+    out.number(data_types.len());
+    for data in data_types {
+        out.number(data.index);
+        out.number(data.params);
+        out.number(data.indices);
+        out.number(data.constructors.len());
+        for &c in &data.constructors {
+            out.number(c);
+        }
+    }
+    out.0.push('\n');
+    out.0
+}
+
+// adds the globals that save wrote to the current module, returning the
+// indices of the ones that checking would have printed, or None if text
+// isn't a valid entry
+pub fn restore(globals: &mut Globals, text: &str) -> Option<Vec<usize>> {
+    let mut input = Reader(text.strip_prefix(FORMAT)?);
+    let mut items = Vec::new();
+    for _ in 0..input.number::<usize>()? {
+        let name = input.string()?;
+        let short_name = match input.number::<u8>()? {
+            0 => None,
+            _ => Some(input.string()?),
+        };
+        let loc = match input.number::<u8>()? {
+            0 => None,
+            _ => Some(Location {
+                file: input.string()?,
+                line: input.number()?,
+                column: input.number()?,
+            }),
+        };
+        let ty = input.expr()?;
+        let def = match input.number::<u8>()? {
+            0 => None,
+            _ => Some((input.number()?, input.def()?)),
+        };
+        items.push((name, short_name, loc, Item { ty, def }));
+    }
+    let mut data_types = Vec::new();
+    for _ in 0..input.number::<usize>()? {
+        let index = input.number()?;
+        let params = input.number()?;
+        let indices = input.number()?;
+        let mut constructors = Vec::new();
+        for _ in 0..input.number::<usize>()? {
+            constructors.push(input.number()?);
+        }
+        data_types.push(DataType { index, params, indices, constructors });
+    }
+    if !input.0.trim().is_empty() {
+        return None;
+    }
+
+    let mut printed = Vec::new();
+    for (name, short_name, loc, item) in items {
+        let index = globals.names.len();
+        match short_name {
+            Some(short_name) => {
+                globals.push(name, short_name, loc, item);
+                printed.push(index);
+            },
+            None => {
+                globals.names.push(name);
+                globals.modules.push(globals.current_module);
+                globals.locations.push(loc);
+                globals.defs.push(item);
+            },
+        }
+    }
+    globals.data_types.extend(data_types);
+    Some(printed)
+}
+
+// everything is written as space separated words, with strings prefixed by
+// their length, so that they can contain anything
+struct Writer(String);
+
+impl Writer {
+    fn word(&mut self, word: &str) {
+        self.0.push(' ');
+        self.0.push_str(word);
+    }
+
+    fn number<T: ToString>(&mut self, n: T) {
+        self.word(&n.to_string());
+    }
+
+    fn string(&mut self, s: &str) {
+        self.word(&format!("{}:{}", s.len(), s));
+    }
+
+    // binders the user didn't name are written as empty names
+    fn name(&mut self, name: &Option<Rc<str>>) {
+        self.string(name.as_deref().unwrap_or(""));
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        self.number(expr.lambda_params);
+        for name in &expr.lambda_names {
+            self.name(name);
+        }
+        self.number(expr.arrow_params.len());
+        for param in &expr.arrow_params {
+            self.number(param.implicit as u8);
+            self.name(&param.name);
+            self.expr(&param.ty);
+        }
+        let (tag, value) = match &expr.head {
+            Ident::Universe(l) => ("u", l.to_string()),
+            Ident::Global(i) => ("g", i.to_string()),
+            Ident::Overload(i) => ("o", i.to_string()),
+            Ident::Local(i) => ("l", i.to_string()),
+            Ident::Meta(i) => ("m", i.to_string()),
+            Ident::Nat(n) => ("n", n.to_string()),
+            Ident::Int(n) => ("i", n.to_string()),
+            Ident::Str(s) => ("s", format!("{}:{}", s.len(), s)),
+            Ident::Hole(_) => unreachable!("items with holes never check"),
+        };
+        self.word(tag);
+        self.word(&value);
+        self.number(expr.tail.len());
+        for arg in &expr.tail {
+            self.expr(arg);
+        }
+    }
+
+    fn def(&mut self, def: &Def) {
+        match def {
+            Def::Body(body) => {
+                self.word("b");
+                self.expr(body);
+            },
+            Def::Cases { major, params, cases } => {
+                self.word("c");
+                self.number(major);
+                self.number(params);
+                self.number(cases.len());
+                for (constructor, case) in cases {
+                    self.number(constructor);
+                    self.def(case);
+                }
+            },
+            Def::Prim(prim) => {
+                self.word("p");
+                self.string(prims::name(*prim));
+            },
+        }
+    }
+}
+
+struct Reader<'a>(&'a str);
+
+impl<'a> Reader<'a> {
+    fn word(&mut self) -> Option<&'a str> {
+        let rest = self.0.trim_start_matches(' ');
+        let end = rest.find(|c: char| c.is_whitespace())
+            .unwrap_or(rest.len());
+        if end == 0 {
+            return None;
+        }
+        self.0 = &rest[end..];
+        Some(&rest[..end])
+    }
+
+    fn number<T: FromStr>(&mut self) -> Option<T> {
+        self.word()?.parse().ok()
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let rest = self.0.trim_start_matches(' ');
+        let colon = rest.find(':')?;
+        let len: usize = rest[..colon].parse().ok()?;
+        let rest = &rest[colon + 1..];
+        let s = rest.get(..len)?;
+        self.0 = &rest[len..];
+        Some(s.into())
+    }
+
+    fn name(&mut self) -> Option<Option<Rc<str>>> {
+        let name = self.string()?;
+        Some(Some(name).filter(|name| !name.is_empty()).map(Rc::from))
+    }
+
+    fn expr(&mut self) -> Option<Expr> {
+        let lambda_params = self.number()?;
+        let mut lambda_names = Vec::with_capacity(lambda_params);
+        for _ in 0..lambda_params {
+            lambda_names.push(self.name()?);
+        }
+        let mut arrow_params = Vec::new();
+        for _ in 0..self.number::<usize>()? {
+            let implicit = self.number::<u8>()? == 1;
+            let name = self.name()?;
+            arrow_params.push(Param { implicit, ty: self.expr()?, name });
+        }
+        let head = match self.word()? {
+            "u" => Ident::Universe(self.number()?),
+            "g" => Ident::Global(self.number()?),
+            "o" => Ident::Overload(self.number()?),
+            "l" => Ident::Local(self.number()?),
+            "m" => Ident::Meta(self.number()?),
+            "n" => Ident::Nat(self.number()?),
+            "i" => Ident::Int(self.number()?),
+            "s" => Ident::Str(self.string()?.into()),
+            _ => return None,
+        };
+        let mut tail = Vec::new();
+        for _ in 0..self.number::<usize>()? {
+            tail.push(self.expr()?);
+        }
+        Some(Expr { lambda_params, lambda_names, arrow_params, head, tail })
+    }
+
+    fn def(&mut self) -> Option<Def> {
+        Some(match self.word()? {
+            "b" => Def::Body(self.expr()?),
+            "c" => {
+                let major = self.number()?;
+                let params = self.number()?;
+                let mut cases = Vec::new();
+                for _ in 0..self.number::<usize>()? {
+                    cases.push((self.number()?, self.def()?));
+                }
+                Def::Cases { major, params, cases }
+            },
+            "p" => Def::Prim(prims::by_name(&self.string()?)?),
+            _ => return None,
+        })
+    }
+}
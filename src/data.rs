@@ -0,0 +1,324 @@
+use ast;
+use error::{LoferError, Location};
+
+use super::{DataType, Def, Expr, Globals, Ident, Item, Param, Scope};
+use super::{binder_name, check_type, deepen, subst};
+
+fn local(i: usize) -> Expr {
+    Expr::from_head(Ident::Local(i))
+}
+
+fn locals(range: ::std::ops::Range<usize>) -> Vec<Expr> {
+    range.map(local).collect()
+}
+
+fn occurs(d: usize, expr: &Expr) -> bool {
+    expr.head == Ident::Global(d)
+        || expr.arrow_params.iter().any(|p| occurs(d, &p.ty))
+        || expr.tail.iter().any(|ex| occurs(d, ex))
+}
+
+// whether an application of d is to exactly the np parameters of the
+// declaration, followed by indices that don't mention d
+fn is_target(d: usize, np: usize, ni: usize, expr: &Expr) -> bool {
+    expr.lambda_params == 0
+        && expr.head == Ident::Global(d)
+        && expr.tail.len() == np + ni
+        && expr.tail[..np] == locals(0..np)[..]
+        && !expr.tail[np..].iter().any(|ex| occurs(d, ex))
+}
+
+// a field is recursive if it is d, or a function returning d that doesn't
+// take d itself, anything else mentioning d can't be eliminated
+fn is_recursive(d: usize, np: usize, ni: usize, field: &Expr)
+    -> Option<bool>
+{
+    if !occurs(d, field) {
+        return Some(false);
+    }
+    let params_ok = field.arrow_params.iter().all(|p| !occurs(d, &p.ty));
+    let mut output = field.clone();
+    output.arrow_params.clear();
+    if params_ok && is_target(d, np, ni, &output) {
+        Some(true)
+    } else {
+        None
+    }
+}
+
+struct Constructor {
+    index: usize,
+    // valid in the context of the data type's parameters
+    ty: Expr,
+    recursive: Vec<usize>,
+}
+
+// checks a data declaration, adding the data type, its constructors and its
+// eliminator to globals, and returning their indices
+pub fn type_check_data(
+    globals: &mut Globals,
+    item: &ast::Item,
+    data: &ast::Data,
+) -> Result<Vec<usize>, LoferError> {
+    let file = &item.file;
+    // the parameters are in scope for the rest of the declaration
+    let mut scope = Scope::default();
+    for param in &data.params {
+        let ty = check_type(globals, file, &param.typ, &scope)?;
+        scope.names.push(param.name.clone().unwrap_or_else(|| "_".into()));
+        scope.types.push(ty);
+    }
+    let np = scope.types.len();
+    let family = check_type(globals, file, &data.typ, &scope)?;
+    let mut family_universe = family.clone();
+    family_universe.arrow_params.clear();
+    let level = match family_universe.universe_level() {
+        Some(level) if family.lambda_params == 0 => level,
+        _ => return Err(LoferError::NotATypeFamily {
+            loc: Location::at(file, data.typ.span()),
+            name: data.name.clone(),
+        }),
+    };
+    let ni = family.arrow_params.len();
+
+    let mut pushed = Vec::new();
+    let d = globals.names.len();
+    let mut ty = family.clone();
+    ty.arrow_params = scope.types
+        .iter()
+        .zip(&scope.names)
+        .map(|(ty, name)| Param {
+            implicit: false,
+            ty: ty.clone(),
+            name: binder_name(name),
+        })
+        .chain(family.arrow_params.iter().cloned())
+        .collect();
+    let loc = Some(Location::at(file, data.span));
+    let item = Item { ty, def: None };
+    globals.push(data.name.clone(), data.name.clone(), loc, item);
+    pushed.push(d);
+
+    // nothing is left of the data type if any of its constructors is wrong
+    let data_len = globals.data_types.len();
+    let result =
+        type_check_constructors(globals, file, data, &scope, d, ni);
+    let constructors = match result {
+        Ok(constructors) => constructors,
+        Err(e) => {
+            globals.truncate(d, data_len);
+            return Err(e);
+        },
+    };
+    pushed.extend(constructors.iter().map(|c| c.index));
+
+    let elim = globals.names.len();
+    let ty = elim_type(d, np, level, &scope.types, &family, &constructors);
+    let cases = constructors
+        .iter()
+        .map(|c| {
+            (c.index, Def::Body(elim_case(elim, np, ni, &constructors, c)))
+        })
+        .collect();
+    let nc = constructors.len();
+    let major = np + 1 + nc + ni;
+    let def = Def::Cases { major, params: np, cases };
+    globals.push(
+        format!("{}_elim", data.name),
+        "elim".into(),
+        Some(Location::at(file, data.span)),
+        Item { ty, def: Some((major + 1, def)) },
+    );
+    pushed.push(elim);
+    globals.data_types.push(DataType {
+        index: d,
+        params: np,
+        indices: ni,
+        constructors: constructors.iter().map(|c| c.index).collect(),
+    });
+    Ok(pushed)
+}
+
+// checks and adds the constructors of the data type d, in the context of its
+// parameters
+fn type_check_constructors(
+    globals: &mut Globals,
+    file: &str,
+    data: &ast::Data,
+    scope: &Scope,
+    d: usize,
+    ni: usize,
+) -> Result<Vec<Constructor>, LoferError> {
+    let np = scope.types.len();
+    let mut constructors = Vec::with_capacity(data.constructors.len());
+    for annotation in &data.constructors {
+        let ty = check_type(globals, file, &annotation.typ, scope)?;
+        let loc = Location::at(file, annotation.span);
+        let mut recursive = Vec::new();
+        for (j, field) in ty.arrow_params.iter().enumerate() {
+            match is_recursive(d, np, ni, &field.ty) {
+                Some(true) => recursive.push(j),
+                Some(false) => (),
+                None => return Err(LoferError::Positivity {
+                    loc,
+                    name: annotation.name.clone(),
+                    data: data.name.clone(),
+                }),
+            }
+        }
+        let mut output = ty.clone();
+        output.arrow_params.clear();
+        if !is_target(d, np, ni, &output) {
+            return Err(LoferError::ConstructorTarget {
+                loc,
+                name: annotation.name.clone(),
+                data: data.name.clone(),
+            });
+        }
+
+        // the parameters can always be worked out from the other arguments
+        let mut full = ty.clone();
+        full.arrow_params = scope.types
+            .iter()
+            .zip(&scope.names)
+            .map(|(ty, name)| Param {
+                implicit: true,
+                ty: ty.clone(),
+                name: binder_name(name),
+            })
+            .chain(ty.arrow_params.iter().cloned())
+            .collect();
+        let index = globals.names.len();
+        let name = annotation.name.clone();
+        let loc = Some(Location::at(file, annotation.span));
+        globals.push(name.clone(), name, loc, Item { ty: full, def: None });
+        constructors.push(Constructor { index, ty, recursive });
+    }
+
+    Ok(constructors)
+}
+
+// {params} -> (M: (indices) -> D params indices -> U) -> methods ->
+//   {indices} -> (x: D params indices) -> M indices x
+fn elim_type(
+    d: usize,
+    np: usize,
+    level: usize,
+    params: &[Expr],
+    family: &Expr,
+    constructors: &[Constructor],
+) -> Expr {
+    let ni = family.arrow_params.len();
+    let nc = constructors.len();
+    let target = |indices| {
+        let mut result = Expr::from_head(Ident::Global(d));
+        result.tail = locals(0..np);
+        result.tail.extend(indices);
+        result
+    };
+    let mut arrow_params: Vec<_> = params
+        .iter()
+        .map(|ty| Param { implicit: true, ty: ty.clone(), name: None })
+        .collect();
+
+    // the family's own parameters already bind the levels after np
+    let mut motive = Expr::universe(level);
+    motive.arrow_params = family.arrow_params.clone();
+    motive.arrow_params.push(Param {
+        implicit: false,
+        ty: target(locals(np..np + ni)),
+        name: None,
+    });
+    arrow_params.push(Param { implicit: false, ty: motive, name: None });
+
+    for (c, constructor) in constructors.iter().enumerate() {
+        let base = np + 1 + c;
+        let fields = deepen(&constructor.ty, np, 1 + c);
+        let nf = fields.arrow_params.len();
+        let mut method = Expr::from_head(Ident::Local(np));
+        method.arrow_params = fields.arrow_params.clone();
+        for &j in &constructor.recursive {
+            let ih_ctx = method.arrow_params.len() + base;
+            // move the field's own parameters past the fields and hypotheses
+            let mut ih = subst(&fields.arrow_params[j].ty, base + j, 0, &[], ih_ctx);
+            let mut applied = local(base + j);
+            applied.tail = locals(ih_ctx..ih_ctx + ih.arrow_params.len());
+            ih.head = Ident::Local(np);
+            ih.tail.drain(0..np);
+            ih.tail.push(applied);
+            method.arrow_params.push(Param {
+                implicit: false,
+                ty: ih,
+                name: None,
+            });
+        }
+        let mut value = Expr::from_head(Ident::Global(constructor.index));
+        value.tail = locals(0..np);
+        value.tail.extend(locals(base..base + nf));
+        method.tail = fields.tail[np..].to_vec();
+        method.tail.push(value);
+        arrow_params.push(Param { implicit: false, ty: method, name: None });
+    }
+
+    let base = np + 1 + nc;
+    let indices = deepen(family, np, 1 + nc);
+    arrow_params.extend(indices.arrow_params.into_iter().map(|mut p| {
+        p.implicit = true;
+        p
+    }));
+    arrow_params.push(Param {
+        implicit: false,
+        ty: target(locals(base..base + ni)),
+        name: None,
+    });
+
+    let mut result = Expr::from_head(Ident::Local(np));
+    result.arrow_params = arrow_params;
+    result.tail = locals(base..base + ni + 1);
+    result
+}
+
+// the body of the eliminator for one constructor, which applies its method to
+// the constructor's fields and to the eliminator applied to each recursive
+// field, in the context of everything but the major premise followed by the
+// constructor's fields
+fn elim_case(
+    elim: usize,
+    np: usize,
+    ni: usize,
+    constructors: &[Constructor],
+    constructor: &Constructor,
+) -> Expr {
+    let nc = constructors.len();
+    let c = constructors
+        .iter()
+        .position(|other| other.index == constructor.index)
+        .unwrap();
+    let fields_start = np + 1 + nc + ni;
+    let nf = constructor.ty.arrow_params.len();
+    let ctx_size = fields_start + nf;
+
+    let mut result = local(np + 1 + c);
+    result.tail = locals(fields_start..ctx_size);
+    for &j in &constructor.recursive {
+        let prev_fields = locals(fields_start..fields_start + j);
+        let field = &constructor.ty.arrow_params[j].ty;
+        // a recursive field that is a function becomes a lambda, whose
+        // parameters are just the field's
+        let field = subst(field, np, 0, &prev_fields, ctx_size);
+        let k = field.arrow_params.len();
+        let mut applied = local(fields_start + j);
+        applied.tail = locals(ctx_size..ctx_size + k);
+
+        let mut rec = Expr::from_head(Ident::Global(elim));
+        rec.lambda_params = k;
+        rec.lambda_names = field.arrow_params.iter()
+            .map(|p| p.name.clone())
+            .collect();
+        rec.tail = locals(0..np + 1 + nc);
+        rec.tail.extend_from_slice(&field.tail[np..]);
+        rec.tail.push(applied);
+        result.tail.push(rec);
+    }
+    result
+}
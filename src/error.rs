@@ -0,0 +1,431 @@
+use std::fmt;
+
+use ast::Span;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Location {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Location {
+    pub fn at(file: &str, span: Span) -> Self {
+        Location { file: file.into(), line: span.line, column: span.column }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum LoferError {
+    Parse {
+        loc: Location,
+        message: String,
+    },
+    UnknownIdentifier {
+        loc: Location,
+        name: String,
+    },
+    // something that should have been a type (or a type family) wasn't
+    Sort {
+        loc: Location,
+        message: String,
+    },
+    TypeMismatch {
+        loc: Location,
+        expr: String,
+        actual: String,
+        expected: String,
+        // the first sub-terms of actual and expected that didn't match
+        difference: Option<Box<(String, String)>>,
+    },
+    MissingDefinition {
+        loc: Location,
+        name: String,
+    },
+    MissingAnnotation {
+        loc: Location,
+        name: String,
+    },
+    TooManyParameters {
+        loc: Location,
+        name: String,
+        params: usize,
+        arity: usize,
+    },
+    // the type of a data declaration has to end in a universe
+    NotATypeFamily {
+        loc: Location,
+        name: String,
+    },
+    // a constructor of data that doesn't return data
+    ConstructorTarget {
+        loc: Location,
+        name: String,
+        data: String,
+    },
+    // a constructor of data that takes a function out of data
+    Positivity {
+        loc: Location,
+        name: String,
+        data: String,
+    },
+    LocalData {
+        loc: Location,
+        name: String,
+    },
+    // expected is None when nothing said what type the lambda should have
+    Lambda {
+        loc: Location,
+        params: usize,
+        expected: Option<String>,
+    },
+    // an implicit argument that nothing determined
+    UnsolvedMeta {
+        loc: Location,
+        name: String,
+    },
+    // an overload whose result can't be applied to any more arguments
+    TooManyArguments {
+        loc: Location,
+        name: String,
+        arity: usize,
+        given: usize,
+    },
+    // an overload whose result can never have the expected type, where
+    // result is None if it would still be a function
+    ResultMismatch {
+        loc: Location,
+        name: String,
+        given: usize,
+        result: Option<String>,
+        expected: String,
+    },
+    // candidates are the overloads that all fit
+    AmbiguousOverload {
+        loc: Location,
+        name: String,
+        candidates: Vec<Candidate>,
+    },
+    // candidates are every overload, with why each didn't fit
+    NoOverload {
+        loc: Location,
+        name: String,
+        candidates: Vec<Candidate>,
+    },
+    // case is the constructors that no clause matches, e.g. `f (suc _) nil`
+    MissingCase {
+        loc: Location,
+        name: String,
+        case: String,
+    },
+    // every value this clause matches is matched by the clauses before it
+    UnreachableClause {
+        loc: Location,
+        name: String,
+    },
+    Pattern {
+        loc: Location,
+        message: String,
+    },
+    // call is a recursive call that doesn't get smaller
+    Termination {
+        loc: Location,
+        name: String,
+        call: String,
+    },
+    Mutual {
+        loc: Location,
+        message: String,
+    },
+    // something that went wrong while running a program's IO
+    Runtime {
+        loc: Location,
+        message: String,
+    },
+    ReadFile {
+        loc: Location,
+        message: String,
+    },
+    // searched is every file that the import could have meant
+    ModuleNotFound {
+        loc: Location,
+        name: String,
+        searched: Vec<String>,
+    },
+    // the files in the cycle, starting and ending with the same one
+    ImportCycle {
+        loc: Location,
+        cycle: Vec<String>,
+    },
+    // loc is where the first of the goals is
+    Holes {
+        loc: Location,
+        goals: Vec<Goal>,
+    },
+}
+
+// an overload of a short name, by its full name
+#[derive(Clone, Debug)]
+pub struct Candidate {
+    pub name: String,
+    pub ty: String,
+    pub failure: Option<String>,
+}
+
+impl fmt::Display for Candidate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.ty)?;
+        if let Some(failure) = &self.failure {
+            for line in failure.lines() {
+                if !line.is_empty() {
+                    write!(f, "\n    {}", line)?;
+                } else {
+                    writeln!(f)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// what a hole has to be filled with, and the locals it can be filled from
+// along with their types
+#[derive(Clone, Debug)]
+pub struct Goal {
+    pub loc: Location,
+    pub name: String,
+    // None if nothing around the hole says what type it has
+    pub ty: Option<String>,
+    pub context: Vec<(String, String)>,
+}
+
+impl fmt::Display for Goal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.ty {
+            Some(ty) => write!(f, "hole {} has type:\n  {}", self.name, ty)?,
+            None => write!(f, "no expected type is known for hole {}",
+                self.name)?,
+        }
+        if !self.context.is_empty() {
+            write!(f, "\n\nin the context:")?;
+            for (name, ty) in &self.context {
+                write!(f, "\n  {} : {}", name, ty)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl LoferError {
+    pub fn location(&self) -> &Location {
+        match self {
+            LoferError::Parse { loc, .. } => loc,
+            LoferError::UnknownIdentifier { loc, .. } => loc,
+            LoferError::Sort { loc, .. } => loc,
+            LoferError::TypeMismatch { loc, .. } => loc,
+            LoferError::MissingDefinition { loc, .. } => loc,
+            LoferError::MissingAnnotation { loc, .. } => loc,
+            LoferError::TooManyParameters { loc, .. } => loc,
+            LoferError::NotATypeFamily { loc, .. } => loc,
+            LoferError::ConstructorTarget { loc, .. } => loc,
+            LoferError::Positivity { loc, .. } => loc,
+            LoferError::LocalData { loc, .. } => loc,
+            LoferError::Lambda { loc, .. } => loc,
+            LoferError::UnsolvedMeta { loc, .. } => loc,
+            LoferError::TooManyArguments { loc, .. } => loc,
+            LoferError::ResultMismatch { loc, .. } => loc,
+            LoferError::AmbiguousOverload { loc, .. } => loc,
+            LoferError::NoOverload { loc, .. } => loc,
+            LoferError::MissingCase { loc, .. } => loc,
+            LoferError::UnreachableClause { loc, .. } => loc,
+            LoferError::Pattern { loc, .. } => loc,
+            LoferError::Termination { loc, .. } => loc,
+            LoferError::Mutual { loc, .. } => loc,
+            LoferError::Runtime { loc, .. } => loc,
+            LoferError::ReadFile { loc, .. } => loc,
+            LoferError::ModuleNotFound { loc, .. } => loc,
+            LoferError::ImportCycle { loc, .. } => loc,
+            LoferError::Holes { loc, .. } => loc,
+        }
+    }
+
+    // the error without its location, for when that is shown some other way
+    pub fn message(&self) -> String {
+        let prefix = format!("{}: ", self.location());
+        self.to_string()[prefix.len()..].into()
+    }
+}
+
+impl fmt::Display for LoferError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: ", self.location())?;
+        match self {
+            LoferError::Parse { message, .. } => {
+                write!(f, "parse error: {}", message)
+            },
+            LoferError::UnknownIdentifier { name, .. } => {
+                write!(f, "could not find term for identifier: {}", name)
+            },
+            LoferError::Sort { message, .. } => {
+                write!(f, "{}", message)
+            },
+            LoferError::TypeMismatch {
+                expr, actual, expected, difference, ..
+            } => {
+                write!(f,
+                    "{} has type:\n  {}\n\nbut it was expected to have type:\n  {}",
+                    expr, actual, expected)?;
+                if let Some(difference) = difference {
+                    let (left, right) = &**difference;
+                    write!(f,
+                        "\n\nthe first difference is:\n  {}\n\nwhich should \
+                        have been:\n  {}",
+                        left, right)?;
+                }
+                Ok(())
+            },
+            LoferError::MissingDefinition { name, .. } => {
+                write!(f, "item {} has no definition", name)
+            },
+            LoferError::MissingAnnotation { name, .. } => {
+                write!(f,
+                    "{} has parameters, so it must have a type annotation",
+                    name)
+            },
+            LoferError::TooManyParameters { name, params, arity, .. } => {
+                write!(f,
+                    "{} is defined with {} parameters but its type only \
+                    accepts {}",
+                    name, params, arity)
+            },
+            LoferError::NotATypeFamily { name, .. } => {
+                write!(f,
+                    "the type of data type {} must be a universe, or an arrow \
+                    expression ending in one",
+                    name)
+            },
+            LoferError::ConstructorTarget { name, data, .. } => {
+                write!(f,
+                    "constructor {} must return {} applied to its parameters \
+                    and then its indices",
+                    name, data)
+            },
+            LoferError::Positivity { name, data, .. } => {
+                write!(f,
+                    "{} occurs in a position that isn't strictly positive in \
+                    the type of constructor {}",
+                    data, name)
+            },
+            LoferError::LocalData { name, .. } => {
+                write!(f,
+                    "data type {} must be declared at the top level", name)
+            },
+            LoferError::Lambda { expected: None, .. } => {
+                write!(f,
+                    "cannot infer the type of a lambda expression, it needs \
+                    to be given an annotation or passed as an argument")
+            },
+            LoferError::Lambda { params, expected: Some(expected), .. } => {
+                write!(f,
+                    "lambda expression with {} parameters was expected to \
+                    have type:\n  {}",
+                    params, expected)
+            },
+            LoferError::UnsolvedMeta { name, .. } => {
+                write!(f, "could not infer an implicit argument of {}", name)
+            },
+            LoferError::TooManyArguments { name, arity, given, .. } => {
+                write!(f,
+                    "{} only accepts {} explicit arguments, but was given {}",
+                    name, arity, given)
+            },
+            LoferError::ResultMismatch {
+                name, result: Some(result), expected, ..
+            } => {
+                write!(f,
+                    "the result of {} is always of type {}, but it was \
+                    expected to have type:\n  {}",
+                    name, result, expected)
+            },
+            LoferError::ResultMismatch {
+                name, given, result: None, expected, ..
+            } => {
+                write!(f,
+                    "{} needs more arguments than the {} it was given, but \
+                    it was expected to have type:\n  {}",
+                    name, given, expected)
+            },
+            LoferError::AmbiguousOverload { name, candidates, .. } => {
+                write!(f, "multiple valid overloads of {}:", name)?;
+                for candidate in candidates {
+                    write!(f, "\n  {}", candidate)?;
+                }
+                Ok(())
+            },
+            LoferError::NoOverload { name, candidates, .. } => {
+                write!(f, "no valid overloads of {}:", name)?;
+                for candidate in candidates {
+                    write!(f, "\n  {}", candidate)?;
+                }
+                Ok(())
+            },
+            LoferError::MissingCase { name, case, .. } => {
+                write!(f, "the clauses of {} don't cover the case:\n  {}",
+                    name, case)
+            },
+            LoferError::UnreachableClause { name, .. } => {
+                write!(f,
+                    "this clause of {} can never match, since the clauses \
+                    before it cover everything it does",
+                    name)
+            },
+            LoferError::Pattern { message, .. } => {
+                write!(f, "{}", message)
+            },
+            LoferError::Termination { name, call, .. } => {
+                write!(f,
+                    "could not show that {} terminates, since no parameter \
+                    gets structurally smaller in the call:\n  {}\n\nmark it \
+                    `partial` if it isn't meant to terminate",
+                    name, call)
+            },
+            LoferError::Mutual { message, .. } => {
+                write!(f, "{}", message)
+            },
+            LoferError::Runtime { message, .. } => {
+                write!(f, "runtime error: {}", message)
+            },
+            LoferError::ReadFile { message, .. } => {
+                write!(f, "could not read file: {}", message)
+            },
+            LoferError::ModuleNotFound { name, searched, .. } => {
+                write!(f, "could not find module {}, looked for:", name)?;
+                for path in searched {
+                    write!(f, "\n  {}", path)?;
+                }
+                Ok(())
+            },
+            LoferError::ImportCycle { cycle, .. } => {
+                write!(f, "modules import each other in a cycle:\n  {}",
+                    cycle.join(" -> "))
+            },
+            LoferError::Holes { goals, .. } => {
+                for (i, goal) in goals.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "\n\n{}: ", goal.loc)?;
+                    }
+                    write!(f, "{}", goal)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+impl ::std::error::Error for LoferError {}
@@ -1,8 +1,12 @@
 use std::iter;
 use line_parser;
 use ast;
+use error::{LoferError, Location};
 
-pub struct ProgramParser(super::line_parser::LineParser);
+// (line index, byte offset of the line in the file, text)
+type RawLine<'a> = (usize, usize, &'a str);
+
+pub struct ProgramParser(line_parser::LineParser, line_parser::ExprParser);
 
 fn count_indent(line: &str) -> usize {
     let mut result = 0;
@@ -16,13 +20,76 @@ fn count_indent(line: &str) -> usize {
     result
 }
 
+// the part of a line before any comment, where a -- inside a string
+// literal doesn't start one
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut prev = None;
+    for (i, c) in line.char_indices() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else if c == '-' && prev == Some('-') {
+            return &line[..i - 1];
+        }
+        prev = Some(c);
+    }
+    line
+}
+
 struct IndentedCode {
     line: String,
+    map: SourceMap,
     sublines: Vec<IndentedCode>,
 }
 
+// one per physical line that was concatenated into a logical line
+struct Segment {
+    offset: usize,
+    file_offset: usize,
+    line_no: usize,
+}
+
+// maps byte offsets in a concatenated line back to positions in the file
+pub struct SourceMap {
+    segments: Vec<Segment>,
+}
+
+impl SourceMap {
+    fn position(&self, offset: usize) -> (usize, usize, usize) {
+        let mut seg = &self.segments[0];
+        for each in &self.segments {
+            if each.offset > offset {
+                break;
+            }
+            seg = each;
+        }
+        let within = offset - seg.offset;
+        (seg.file_offset + within, seg.line_no, within + 1)
+    }
+
+    pub fn span(&self, start: usize, end: usize) -> ast::Span {
+        let (file_start, line, column) = self.position(start);
+        // end is exclusive, so find the segment of the last character
+        let file_end = if end > start {
+            self.position(end - 1).0 + 1
+        } else {
+            file_start
+        };
+        ast::Span { start: file_start, end: file_end, line, column }
+    }
+}
+
 fn split_indenting<'a, I>(input: I) -> Vec<IndentedCode>
-    where I: Iterator<Item=&'a str>
+    where I: Iterator<Item=RawLine<'a>>
 {
     let mut peek_input = input.peekable();
     split_indenting_helper(&mut peek_input, 0)
@@ -32,33 +99,33 @@ fn split_indenting<'a, I>(input: I) -> Vec<IndentedCode>
 // than the line length
 // i.e. discards empty lines
 fn find_line<'a, I>(input: &mut iter::Peekable<I>) -> Option<usize>
-    where I: Iterator<Item=&'a str>
+    where I: Iterator<Item=RawLine<'a>>
 {
-    let mut result = None;
-    loop {
-        if let Some(line) = input.peek() {
-            let count = count_indent(*line);
-            if count != line.len() {
-                result = Some(count);
-                break;
-            }
-        } else {
-            // no lines left, return None
-            break;
+    while let Some(&(_, _, line)) = input.peek() {
+        let count = count_indent(line);
+        if count != line.len() {
+            return Some(count);
         }
         // empty/whitespace line, skip
         input.next();
     }
-    result
+    // no lines left
+    None
 }
 
-fn concat_lines<'a, I>(input: &mut iter::Peekable<I>) -> String
-    where I: Iterator<Item=&'a str>
+fn concat_lines<'a, I>(input: &mut iter::Peekable<I>) -> (String, SourceMap)
+    where I: Iterator<Item=RawLine<'a>>
 {
     let mut result = String::new();
+    let mut segments = Vec::new();
     while find_line(input).is_some() {
-        let line = input.next().unwrap();
-        if line.chars().rev().next().unwrap() == '\\' {
+        let (line_index, file_offset, line) = input.next().unwrap();
+        segments.push(Segment {
+            offset: result.len(),
+            file_offset,
+            line_no: line_index + 1,
+        });
+        if line.ends_with('\\') {
             let line = line.get(..line.len() - 1).unwrap();
             result.push_str(line);
         } else {
@@ -66,14 +133,14 @@ fn concat_lines<'a, I>(input: &mut iter::Peekable<I>) -> String
             break;
         }
     }
-    result
+    (result, SourceMap { segments })
 }
 
 fn split_indenting_helper<'a, I>(
     input: &mut iter::Peekable<I>,
     min_indent: usize,
 ) -> Vec<IndentedCode>
-    where I: Iterator<Item=&'a str>
+    where I: Iterator<Item=RawLine<'a>>
 {
     let mut result = Vec::new();
 
@@ -90,78 +157,294 @@ fn split_indenting_helper<'a, I>(
     }
 
     while find_line(input) == Some(indent) {
-        let line = concat_lines(input);
+        let (line, map) = concat_lines(input);
         let sublines = split_indenting_helper(input, indent + 1);
-        let next = IndentedCode { line, sublines };
+        let next = IndentedCode { line, map, sublines };
         result.push(next);
     }
     result
 }
 
-type Error<'a> = ::lalrpop_util::ParseError<usize,
-    line_parser::Token<'a>, &'a str>;
+impl Default for ProgramParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl ProgramParser {
     pub fn new() -> Self {
         let inner = line_parser::LineParser::new();
+        let expr = line_parser::ExprParser::new();
 
-        ProgramParser(inner)
+        ProgramParser(inner, expr)
     }
 
-    pub fn parse<'a>(self: &Self, input: &'a str)
-        -> Vec<ast::Item>
+    pub fn parse(&self, file: &str, input: &str)
+        -> Result<Vec<ast::Item>, LoferError>
     {
+        let (result, mut errors) = self.parse_recover(file, input);
+        if !errors.is_empty() {
+            Err(errors.remove(0))
+        } else {
+            Ok(result)
+        }
+    }
+
+    // lines that fail to parse are reported and skipped, along with any
+    // annotation that they might have been defining
+    pub fn parse_recover(&self, file: &str, input: &str)
+        -> (Vec<ast::Item>, Vec<LoferError>)
+    {
+        let mut file_offset = 0;
         let indented = split_indenting(input
             .split("\n")
-            .map(|line| line
-                .splitn(2, "--")
-                .next()
-                .unwrap()
-            )
+            .enumerate()
+            .map(|(i, line)| {
+                let start = file_offset;
+                file_offset += line.len() + 1;
+                (i, start, strip_comment(line))
+            })
         );
-        self.from_indented(&indented).unwrap() // can't return the error
-             // because it would outlive the thing being parsed
-             // by switching IndentedCode back to str/Vec<str> we could
-             // fix that?
-             // but we can't parse functions from a Vec<str>
-             // so we'd actually need to refactor the parser somehow
-             // id rather just not report errors and save that for the
-             // self hosting parser
-    }
-
-    fn from_indented<'a>(self: &Self, indented: &'a Vec<IndentedCode>)
-        -> Result<Vec<ast::Item>, Error<'a>>
+        let mut errors = Vec::new();
+        let result = self.items_from_indented(file, &indented, &mut errors);
+        (result, errors)
+    }
+
+    // parses a single expression, like one given on the command line
+    pub fn parse_expr(&self, file: &str, input: &str)
+        -> Result<ast::Expr, LoferError>
     {
-        let mut result = Vec::with_capacity(indented.len());
+        let indented = IndentedCode {
+            line: input.into(),
+            map: SourceMap {
+                segments: vec![Segment { offset: 0, file_offset: 0, line_no: 1 }],
+            },
+            sublines: Vec::new(),
+        };
+        self.1.parse(&indented.map, &indented.line)
+            .map_err(|err| parse_error(file, &indented, err))
+    }
+
+    fn items_from_indented(
+        &self,
+        file: &str,
+        indented: &[IndentedCode],
+        errors: &mut Vec<LoferError>,
+    ) -> Vec<ast::Item> {
+        let mut result: Vec<ast::Item> = Vec::with_capacity(indented.len());
 
         let mut annotation = None;
         for indented in indented {
-            let output = self.0.parse(&indented.line);
-            if let Err(err) = output {
-                panic!("Parse error on \"{}\", {:?}", &indented.line, err);
-            }
-            let output = output.unwrap();
+            let output = match self.0.parse(&indented.map, &indented.line) {
+                Ok(output) => output,
+                Err(err) => {
+                    errors.push(parse_error(file, indented, err));
+                    annotation = None;
+                    continue;
+                },
+            };
             if let ast::Line::Function(definition) = output {
-                let associated = self.from_indented(&indented.sublines)?;
+                let mut associated =
+                    self.items_from_indented(file, &indented.sublines, errors);
+                reject_imports(file, &mut associated, errors);
+                // consecutive definitions of the same name are clauses of
+                // a single pattern matching definition
+                if annotation.is_none() {
+                    if let Some(prev) = result.last_mut() {
+                        let same_name = prev.definition
+                            .as_ref()
+                            .is_some_and(|d| d.fname == definition.fname);
+                        if same_name {
+                            prev.clauses.push(definition);
+                            prev.associated.append(&mut associated);
+                            continue;
+                        }
+                    }
+                }
                 let program = ast::Item {
                     annotation: annotation.take(),
                     definition: Some(definition),
                     associated,
+                    ..empty_item(file)
                 };
                 result.push(program);
             } else if let ast::Line::Annotation(it) = output {
                 if annotation.is_some() {
                     let program = ast::Item {
                         annotation: annotation.take(),
-                        definition: None,
-                        associated: Vec::new(),
+                        ..empty_item(file)
                     };
                     result.push(program);
                 }
                 annotation = Some(it);
+            } else if let ast::Line::Data(mut data) = output {
+                if annotation.is_some() {
+                    result.push(ast::Item {
+                        annotation: annotation.take(),
+                        ..empty_item(file)
+                    });
+                }
+                self.constructors(file, &indented.sublines, &mut data, errors);
+                result.push(ast::Item {
+                    data: Some(data),
+                    ..empty_item(file)
+                });
+            } else if let ast::Line::Mutual(span) = output {
+                if annotation.is_some() {
+                    result.push(ast::Item {
+                        annotation: annotation.take(),
+                        ..empty_item(file)
+                    });
+                }
+                let mut items =
+                    self.items_from_indented(file, &indented.sublines, errors);
+                reject_imports(file, &mut items, errors);
+                result.push(ast::Item {
+                    mutual: Some(ast::Mutual { items, span }),
+                    ..empty_item(file)
+                });
+            } else if let ast::Line::Import(import) = output {
+                if annotation.is_some() {
+                    result.push(ast::Item {
+                        annotation: annotation.take(),
+                        ..empty_item(file)
+                    });
+                }
+                result.push(ast::Item {
+                    import: Some(import),
+                    ..empty_item(file)
+                });
             }
         }
+        if annotation.is_some() {
+            result.push(ast::Item {
+                annotation,
+                ..empty_item(file)
+            });
+        }
+
+        result
+    }
 
-        Ok(result)
+    // the lines indented under a data declaration are more constructors
+    fn constructors(
+        &self,
+        file: &str,
+        indented: &[IndentedCode],
+        data: &mut ast::Data,
+        errors: &mut Vec<LoferError>,
+    ) {
+        for indented in indented {
+            match self.0.parse(&indented.map, &indented.line) {
+                Ok(ast::Line::Annotation(it))
+                    if !it.is_post && !it.is_partial =>
+                {
+                    data.constructors.push(it);
+                },
+                Ok(_) => {
+                    let start = count_indent(&indented.line);
+                    let span = indented.map.span(start, indented.line.len());
+                    errors.push(LoferError::Parse {
+                        loc: Location::at(file, span),
+                        message: format!(
+                            "expected a constructor of {}, e.g. `c: {}`",
+                            data.name, data.name,
+                        ),
+                    });
+                },
+                Err(err) => errors.push(parse_error(file, indented, err)),
+            }
+        }
+    }
+}
+
+// an item with nothing in it yet, for filling in whichever part a line was
+fn empty_item(file: &str) -> ast::Item {
+    ast::Item {
+        file: file.into(),
+        annotation: None,
+        definition: None,
+        clauses: Vec::new(),
+        associated: Vec::new(),
+        data: None,
+        mutual: None,
+        import: None,
+    }
+}
+
+// imports are only allowed at the top level of a file, so any others are
+// reported and dropped
+fn reject_imports(
+    file: &str,
+    items: &mut Vec<ast::Item>,
+    errors: &mut Vec<LoferError>,
+) {
+    items.retain(|item| match &item.import {
+        Some(import) => {
+            errors.push(LoferError::Parse {
+                loc: Location::at(file, import.span),
+                message: "imports have to be at the top level of a file"
+                    .into(),
+            });
+            false
+        },
+        None => true,
+    });
+}
+
+// the contents of a string literal, between its quotes
+pub fn unescape(quoted: &str) -> Result<String, &'static str> {
+    let mut result = String::with_capacity(quoted.len());
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('"') => result.push('"'),
+            Some('\\') => result.push('\\'),
+            _ => return Err("unknown escape sequence in string literal"),
+        }
+    }
+    Ok(result)
+}
+
+type Error<'a> = ::lalrpop_util::ParseError<usize,
+    line_parser::Token<'a>, &'a str>;
+
+// lalrpop's own Display reports offsets into the concatenated line, which
+// mean nothing to the user, so describe the error without them
+fn parse_error(file: &str, indented: &IndentedCode, err: Error)
+    -> LoferError
+{
+    use lalrpop_util::ParseError;
+    let (offset, mut message) = match &err {
+        ParseError::InvalidToken { location } => {
+            (*location, "invalid token".to_string())
+        },
+        ParseError::UnrecognizedToken { token: Some((l, t, _)), .. } => {
+            (*l, format!("unrecognized token `{}`", t))
+        },
+        ParseError::UnrecognizedToken { token: None, .. } => {
+            (indented.line.len(), "unexpected end of line".to_string())
+        },
+        ParseError::ExtraToken { token: (l, t, _) } => {
+            (*l, format!("extra token `{}`", t))
+        },
+        ParseError::User { error } => (0, error.to_string()),
+    };
+    if let ParseError::UnrecognizedToken { expected, .. } = &err {
+        if !expected.is_empty() {
+            message.push_str(&format!(", expected one of {}",
+                expected.join(", ")));
+        }
+    }
+    let span = indented.map.span(offset, offset);
+    LoferError::Parse {
+        loc: Location::at(file, span),
+        message,
     }
 }
@@ -2,18 +2,77 @@
 extern crate lalrpop_util;
 
 pub mod ast;
+mod cache;
+mod data;
+mod error;
 mod indent_parser;
+mod modules;
+mod patterns;
+mod pretty;
+mod prims;
+mod repl;
+mod runtime;
+mod termination;
+mod unify;
 
 // why am I even using lalrpop for such a simple grammar
 // f : (x1: A) -> (x2: B) -> (x3: C) -> D
 // f x1 x2 x3 = a (b c (d e) f) g h
-lalrpop_mod!(line_parser);
+lalrpop_mod!(#[allow(clippy::all, unused_parens)] line_parser);
 
+pub use error::{Candidate, Goal, LoferError, Location};
 pub use indent_parser::ProgramParser;
+pub use modules::Modules;
+pub use repl::repl;
+pub use runtime::run;
+
+use std::mem;
+use std::rc::Rc;
+
+use pretty::Pretty;
+use unify::{ChoiceKey, Difference, Hole, Metas, first_meta, subtype};
 
 struct Item {
     ty: Expr,
-    def: Option<(usize, Expr)>,
+    def: Option<(usize, Def)>,
+}
+
+// how a global reduces once it has been given its parameters
+enum Def {
+    // by substituting them into an expression
+    Body(Expr),
+    // only once the parameter at major is an application of one of these
+    // constructors, whose fields (after the data type's parameters) then
+    // replace it in the context of that constructor's case, which is how
+    // eliminators compute
+    Cases { major: usize, params: usize, cases: Vec<(usize, Def)> },
+    // natively, once they are all literals
+    Prim(prims::Prim),
+}
+
+// what pattern matching needs to know about a data declaration
+struct DataType {
+    index: usize,
+    params: usize,
+    indices: usize,
+    constructors: Vec<usize>,
+}
+
+impl DataType {
+    // declared straight after the constructors
+    fn eliminator(&self) -> usize {
+        self.index + self.constructors.len() + 1
+    }
+}
+
+// switches for the rules of the type theory being checked
+#[derive(Clone, Copy, Default)]
+pub struct Options {
+    // U i is also in U j whenever i <= j
+    pub cumulative: bool,
+    // every universe is the same one, which is in itself, for experimenting
+    // with paradoxes
+    pub type_in_type: bool,
 }
 
 pub struct Globals {
@@ -21,151 +80,778 @@ pub struct Globals {
     defs: Vec<Item>,
     short_names: Vec<String>,
     overloads: Vec<Vec<usize>>,
+    data_types: Vec<DataType>,
+    // the module each global was defined in, as an index into module_names,
+    // where the builtins are in the module with no name
+    modules: Vec<usize>,
+    module_names: Vec<String>,
+    current_module: usize,
+    // where each global was declared, which the builtins weren't
+    locations: Vec<Option<Location>>,
+    options: Options,
+}
+
+impl Default for Globals {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Globals {
     pub fn new() -> Globals {
-        Globals {
+        Globals::with_options(Options::default())
+    }
+
+    pub fn with_options(options: Options) -> Globals {
+        let mut globals = Globals {
             names: Vec::new(),
             defs: Vec::new(),
             short_names: Vec::new(),
             overloads: Vec::new(),
+            data_types: Vec::new(),
+            modules: Vec::new(),
+            module_names: vec![String::new()],
+            current_module: 0,
+            locations: Vec::new(),
+            options,
+        };
+        prims::add_builtins(&mut globals);
+        globals
+    }
+
+    // only meant to be changed before anything is checked
+    pub fn options_mut(&mut self) -> &mut Options {
+        &mut self.options
+    }
+
+    // globals pushed from now on are in the module called name, so that they
+    // can also be referred to as name.global
+    pub fn enter_module(&mut self, name: &str) {
+        let name = name.to_string();
+        self.current_module = match get_index(&self.module_names, &name) {
+            Some(m) => m,
+            None => {
+                self.module_names.push(name);
+                self.module_names.len() - 1
+            },
+        };
+    }
+
+    // forgets every global from index len onwards, and every data type from
+    // data_len, as if they had never been pushed
+    fn truncate(&mut self, len: usize, data_len: usize) {
+        self.names.truncate(len);
+        self.defs.truncate(len);
+        self.modules.truncate(len);
+        self.locations.truncate(len);
+        for overloads in &mut self.overloads {
+            overloads.retain(|&i| i < len);
         }
+        // short names are only ever added at the end
+        while self.overloads.last().is_some_and(|o| o.is_empty()) {
+            self.overloads.pop();
+            self.short_names.pop();
+        }
+        self.data_types.truncate(data_len);
     }
-}
 
-pub fn type_check_all(globals: &mut Globals, programs: Vec<ast::Item>) {
-    for item in &programs {
-        let (name, short_name, item) = type_check_function(globals, item);
-        println!("{}: {}", name, item.ty);
-        let index = globals.names.len();
-        globals.names.push(name);
-        if let Some(i) = get_index(&globals.short_names, &short_name) {
-            globals.overloads[i].push(index);
+    fn push(
+        &mut self,
+        name: String,
+        short_name: String,
+        loc: Option<Location>,
+        item: Item,
+    ) {
+        let index = self.names.len();
+        self.names.push(name);
+        self.modules.push(self.current_module);
+        self.locations.push(loc);
+        if let Some(i) = get_index(&self.short_names, &short_name) {
+            self.overloads[i].push(index);
         } else {
-            globals.short_names.push(short_name);
-            globals.overloads.push(vec![index]);
+            self.short_names.push(short_name);
+            self.overloads.push(vec![index]);
         }
-        globals.defs.push(item);
+        self.defs.push(item);
+    }
+}
+
+pub fn type_check_all(globals: &mut Globals, programs: Vec<ast::Item>)
+    -> Result<(), LoferError>
+{
+    let (checked, mut errors) = check_items(globals, &programs, false);
+    for i in checked {
+        print_global(globals, i);
+    }
+    if !errors.is_empty() {
+        return Err(errors.remove(0));
     }
 
     print!("Successfully type-checked all items!\n\n");
+    Ok(())
 }
 
-fn type_check_function(
-    globals: &Globals,
-    fun: &ast::Item,
-) -> (String, String, Item) {
-    for _ in &fun.associated {
-        unimplemented!();
+// like type_check_all, but items that fail are reported and then assumed
+// to exist anyway, so that one mistake doesn't hide the rest
+// an item is kept as a postulate if its annotation still makes sense
+pub fn type_check_all_recover(globals: &mut Globals, programs: Vec<ast::Item>)
+    -> Vec<LoferError>
+{
+    let (checked, errors) = check_items(globals, &programs, true);
+    for i in checked {
+        print_global(globals, i);
     }
-    if fun.annotation.is_none() {
-        if fun.definition.is_none() {
-            panic!("Found neither annotation nor definition?");
-        } else if fun.definition.as_ref().unwrap().vars.len() > 0 {
-            panic!("Terms with parameters must have a type annotation");
-        } else {
-            unimplemented!();
+
+    if errors.is_empty() {
+        print!("Successfully type-checked all items!\n\n");
+    }
+    errors
+}
+
+// checks items without printing anything, returning the indices of the
+// globals that checked in the order that they would be printed, along with
+// every error if keep_going, or else the first one
+fn check_items(
+    globals: &mut Globals,
+    programs: &[ast::Item],
+    keep_going: bool,
+) -> (Vec<usize>, Vec<LoferError>) {
+    let mut checked = Vec::new();
+    let mut errors = Vec::new();
+    for item in programs {
+        let pushed = type_check_item(globals, item, &mut errors);
+        if !errors.is_empty() && !keep_going {
+            errors.truncate(1);
+            break;
         }
+        checked.extend(pushed);
     }
-    let annotation = fun.annotation.as_ref().unwrap();
-    let mut ty = convert_expr(
-        &globals.names,
-        &globals.short_names,
-        &Default::default(),
-        annotation.typ.clone()
-    );
-    if let Err(e) = sort_check_expr(
-        &globals.defs,
-        &globals.overloads,
-        &Context::new(&[]),
-        &mut ty,
-    ) {
-        panic!(
-            "Type check error during annotation of {}\n\n{}\n\n",
-            annotation.name,
-            e,
-        );
+    (checked, errors)
+}
+
+// where errors in terms given to evaluate are reported
+const EVAL_FILE: &str = "<eval>";
+
+// evaluates a checked global to normal form, printed with the names the user
+// wrote, where name is either its full name or an unambiguous short name
+pub fn evaluate(globals: &Globals, name: &str) -> Result<String, LoferError> {
+    let index = find_global(globals, EVAL_FILE, name)?;
+    let mut value = Expr::from_head(Ident::Global(index));
+    eval(&globals.defs, &mut value, 0);
+    Ok(Pretty::new(globals, &[], &value).to_string())
+}
+
+// the global called name, either by its full name or an unambiguous short
+// name, where errors are reported as being in file
+fn find_global(globals: &Globals, file: &str, name: &str)
+    -> Result<usize, LoferError>
+{
+    let loc = Location { file: file.into(), line: 1, column: 1 };
+    let name = name.to_string();
+    if let Some(i) = get_index(&globals.names, &name) {
+        Ok(i)
+    } else if let Some(i) = get_index(&globals.short_names, &name) {
+        match &globals.overloads[i][..] {
+            &[index] => Ok(index),
+            overloads => Err(LoferError::AmbiguousOverload {
+                loc,
+                name,
+                candidates: overloads.iter()
+                    .map(|&c| candidate(globals, c, None))
+                    .collect(),
+            }),
+        }
+    } else {
+        Err(LoferError::UnknownIdentifier { loc, name })
     }
-    // maybe we want to store both eval and non-eval versions?
-    eval(&globals.defs, &mut ty, 0);
+}
 
-    if fun.definition.is_none() {
-        if !annotation.is_post {
-            panic!("Item {} has no definition", annotation.name);
+// type checks an expression in the context of every checked global, and
+// evaluates it to normal form
+pub fn evaluate_expr(globals: &Globals, source: &str)
+    -> Result<String, LoferError>
+{
+    let parser = ProgramParser::new();
+    let expr = parser.parse_expr(EVAL_FILE, source)?;
+    let scope = Scope::default();
+    let (mut value, _) = infer_expr(globals, EVAL_FILE, &expr, &scope)?;
+    eval(&globals.defs, &mut value, 0);
+    Ok(Pretty::new(globals, &[], &value).to_string())
+}
+
+// a global that a name could refer to, for editors to show
+pub struct Declaration {
+    pub name: String,
+    pub ty: String,
+    // None for the builtins
+    pub loc: Option<Location>,
+}
+
+// every global that name could refer to, which is the last one with that
+// full name, any that overload it as a short name, or else what it means as
+// a qualified name
+pub fn declarations(globals: &Globals, name: &str) -> Vec<Declaration> {
+    let name = name.to_string();
+    let mut indices: Vec<_> = get_index(&globals.names, &name)
+        .into_iter()
+        .collect();
+    if let Some(s) = get_index(&globals.short_names, &name) {
+        for &i in &globals.overloads[s] {
+            if !indices.contains(&i) {
+                indices.push(i);
+            }
         }
-        (
-            annotation.name.clone(),
+    }
+    if indices.is_empty() && name.contains('.') {
+        let span = ast::Span::default();
+        if let Ok(Ident::Global(i)) = resolve_qualified(globals, &name, span) {
+            indices.push(i);
+        }
+    }
+    indices.sort();
+    indices.into_iter().map(|i| Declaration {
+        name: globals.names[i].clone(),
+        ty: Pretty::new(globals, &[], &globals.defs[i].ty).to_string(),
+        loc: globals.locations[i].clone(),
+    }).collect()
+}
+
+// checks a top level item, adding everything it declares to globals, and
+// returning the indices of whatever checked successfully
+fn type_check_item(
+    globals: &mut Globals,
+    item: &ast::Item,
+    errors: &mut Vec<LoferError>,
+) -> Vec<usize> {
+    // imports are loaded before anything in the file is checked
+    if item.import.is_some() {
+        return Vec::new();
+    }
+    if let Some(data) = &item.data {
+        return match data::type_check_data(globals, item, data) {
+            Ok(pushed) => pushed,
+            Err(e) => {
+                errors.push(e);
+                Vec::new()
+            },
+        };
+    }
+    if let Some(mutual) = &item.mutual {
+        for each in &mutual.items {
+            let (span, message) = if let Some(data) = &each.data {
+                (data.span, "data types can't be declared in a mutual block")
+            } else if let Some(inner) = &each.mutual {
+                (inner.span, "mutual blocks can't be nested")
+            } else if each.annotation.is_none() {
+                let span = each.definition.as_ref().unwrap().span;
+                (span, "every item in a mutual block needs an annotation")
+            } else {
+                continue;
+            };
+            errors.push(LoferError::Mutual {
+                loc: Location::at(&each.file, span),
+                message: message.into(),
+            });
+            return Vec::new();
+        }
+        let members: Vec<_> = mutual.items.iter().collect();
+        return type_check_group(globals, &members, errors);
+    }
+    if item.annotation.is_none() {
+        // without an annotation, nothing can refer to it until it's checked
+        return match type_check_function(globals, item, &Scope::default()) {
+            Ok((name, short_name, checked)) => {
+                let index = globals.names.len();
+                let loc = Some(item_location(item));
+                globals.push(name, short_name, loc, checked);
+                vec![index]
+            },
+            Err(e) => {
+                errors.push(e);
+                Vec::new()
+            },
+        };
+    }
+    type_check_group(globals, &[item], errors)
+}
+
+// checks annotated items that can refer to themselves and each other, by
+// adding them to globals as postulates, and only giving them their
+// definitions once they are all known to terminate
+fn type_check_group(
+    globals: &mut Globals,
+    items: &[&ast::Item],
+    errors: &mut Vec<LoferError>,
+) -> Vec<usize> {
+    let mut members = Vec::with_capacity(items.len());
+    for &item in items {
+        let annotation = item.annotation.as_ref().unwrap();
+        let scope = Scope::default();
+        let mut ty = match check_annotation(globals, item, annotation, &scope)
+        {
+            Ok(ty) => ty,
+            Err(e) => {
+                errors.push(e);
+                continue;
+            },
+        };
+        name_params(globals, item, annotation, &mut ty);
+        let short_name = item.definition
+            .as_ref()
+            .map_or(&annotation.name, |def| &def.fname);
+        members.push((item, globals.names.len()));
+        globals.push(
             annotation.name.clone(),
+            short_name.clone(),
+            Some(item_location(item)),
             Item { ty, def: None },
-        )
-    } else {
+        );
+    }
+
+    let mut defs = Vec::with_capacity(members.len());
+    let mut helpers = Vec::new();
+    for (item, index) in members {
+        let annotation = item.annotation.as_ref().unwrap();
+        let ty = globals.defs[index].ty.clone();
+        let scope = Scope::default();
+        let first_helper = globals.names.len();
+        match check_definition(globals, item, annotation, &ty, &scope) {
+            Ok(def) => {
+                // the associated items were each added once their own
+                // associated items were
+                let mut items = Vec::new();
+                associated_items(item, &mut items);
+                helpers.extend(items.into_iter().zip(first_helper..));
+                defs.push((item, index, def));
+            },
+            Err(e) => errors.push(e),
+        }
+    }
+    if let Err(e) = termination::check(globals, &defs, &helpers) {
+        errors.push(e);
+        return Vec::new();
+    }
+    defs.into_iter().map(|(_, index, def)| {
+        globals.defs[index].def = def;
+        index
+    }).collect()
+}
+
+// the associated items of an item, nested ones first, in the order that
+// check_associated adds them to globals
+fn associated_items<'a>(item: &'a ast::Item, items: &mut Vec<&'a ast::Item>) {
+    for each in &item.associated {
+        associated_items(each, items);
+        items.push(each);
+    }
+}
+
+// where an item is declared, which is its annotation if it has one
+fn item_location(item: &ast::Item) -> Location {
+    let span = match (&item.annotation, &item.definition) {
+        (Some(annotation), _) => annotation.span,
+        (None, Some(definition)) => definition.span,
+        _ => ast::Span::default(),
+    };
+    Location::at(&item.file, span)
+}
+
+fn print_global(globals: &Globals, i: usize) {
+    println!("{}: {}",
+        globals.names[i], Pretty::new(globals, &[], &globals.defs[i].ty));
+}
+
+// the parameters of the functions that an associated item is nested in,
+// and the associated items before it, which it can refer to
+#[derive(Clone, Default)]
+struct Scope {
+    names: Vec<String>,
+    types: Vec<Expr>,
+    helpers: Vec<Helper>,
+}
+
+// associated items are lifted into globals that take the parameters of
+// their parents first, so they can't be referred to from outside
+#[derive(Clone)]
+struct Helper {
+    name: String,
+    index: usize,
+    captured: usize,
+}
+
+fn type_check_function(
+    globals: &mut Globals,
+    fun: &ast::Item,
+    scope: &Scope,
+) -> Result<(String, String, Item), LoferError> {
+    if fun.annotation.is_none() {
+        // the parser never produces an item with neither
         let definition = fun.definition.as_ref().unwrap();
-        let var_names = &definition.vars;
+        if !definition.patterns.is_empty() || !fun.clauses.is_empty() {
+            return Err(LoferError::MissingAnnotation {
+                loc: Location::at(&fun.file, definition.span),
+                name: definition.fname.clone(),
+            });
+        }
+        // with no parameters, the type is just whatever the body has
+        let mut inner = scope.clone();
+        check_associated(globals, fun, &definition.fname, &mut inner)?;
+        let (def, ty) =
+            infer_expr(globals, &fun.file, &definition.body, &inner)?;
+        return Ok((
+            definition.fname.clone(),
+            definition.fname.clone(),
+            Item { ty, def: Some((0, Def::Body(def))) },
+        ));
+    }
+    let annotation = fun.annotation.as_ref().unwrap();
+    let mut ty = check_annotation(globals, fun, annotation, scope)?;
+    name_params(globals, fun, annotation, &mut ty);
+    let def = check_definition(globals, fun, annotation, &ty, scope)?;
+    let short_name = fun.definition
+        .as_ref()
+        .map_or(&annotation.name, |def| &def.fname);
+    Ok((annotation.name.clone(), short_name.clone(), Item { ty, def }))
+}
+
+// names the parameters that the annotation left unnamed after the first
+// variable that a clause binds them to, for printing
+fn name_params(
+    globals: &Globals,
+    fun: &ast::Item,
+    annotation: &ast::Annotation,
+    ty: &mut Expr,
+) {
+    for clause in fun.definition.iter().chain(&fun.clauses) {
+        let patterns = bind_params(&annotation.typ, ty, &clause.patterns);
+        for (param, pattern) in ty.arrow_params.iter_mut().zip(&patterns) {
+            let is_var = pattern.args.is_empty()
+                && !patterns::is_constructor(globals, &pattern.name);
+            if param.name.is_none() && is_var {
+                param.name = binder_name(&pattern.name);
+            }
+        }
+    }
+}
+
+// "_" stands for a binder that the user didn't name
+fn binder_name(name: &str) -> Option<Rc<str>> {
+    if name == "_" {
+        None
+    } else {
+        Some(name.into())
+    }
+}
+
+// checks the definition of an annotated item against its type, if it has
+// one, which only postulates don't have to
+fn check_definition(
+    globals: &mut Globals,
+    fun: &ast::Item,
+    annotation: &ast::Annotation,
+    ty: &Expr,
+    scope: &Scope,
+) -> Result<Option<(usize, Def)>, LoferError> {
+    if let Some(definition) = &fun.definition {
+        if patterns::is_matching(globals, fun) {
+            let def = patterns::check_clauses(
+                globals,
+                fun,
+                annotation,
+                ty,
+                scope,
+            )?;
+            return Ok(Some(def));
+        }
+        let var_names: Vec<_> =
+            bind_params(&annotation.typ, ty, &definition.patterns)
+            .into_iter()
+            .map(|p| p.name)
+            .collect();
         let param_num = var_names.len();
 
-        let mut def = convert_expr(
-            &globals.names,
-            &globals.short_names,
-            &Context::new(&var_names),
+        let must_bind = !annotation.is_post || !fun.associated.is_empty();
+        if must_bind && param_num > ty.arrow_params.len() {
+            let arity = ty.arrow_params.iter().filter(|p| !p.implicit).count();
+            return Err(LoferError::TooManyParameters {
+                loc: Location::at(&fun.file, definition.span),
+                name: definition.fname.clone(),
+                params: definition.patterns.len(),
+                arity,
+            });
+        }
+
+        let mut inner = scope.clone();
+        inner.names.extend(var_names);
+        let mut result = ty.clone();
+        if must_bind {
+            inner.types.extend(
+                result.arrow_params.drain(0..param_num).map(|p| p.ty)
+            );
+        }
+
+        check_associated(globals, fun, &annotation.name, &mut inner)?;
+
+        let (mut def, mut def_spans) = convert_expr(
+            globals,
+            &inner.helpers,
+            &Context::new(&inner.names),
             definition.body.clone(),
-        );
+        ).map_err(|e| e.report(globals, &fun.file, &inner.names))?;
 
         if !annotation.is_post {
-            let mut result = ty.clone();
-            let bindings: Vec<_> = result
-                .arrow_params
-                .drain(0..param_num)
-                .collect();
-
-            if let Err(e) = type_check_expr(
-                &globals.defs,
-                &globals.overloads,
-                &Context::new(&bindings),
+            let mut metas = Metas::default();
+            type_check_expr(
+                globals,
+                &mut metas,
+                &Context::new(&inner.types),
                 &mut def,
+                &mut def_spans,
                 Some(&result),
-            ) {
-                panic!(
-                    "Type check error during definition of {}\n\n{}\n\n",
-                    definition.fname,
-                    e,
-                );
+            ).and_then(|_| {
+                metas.finish(&mut def, inner.types.len())
+            }).map_err(|e| e.report(globals, &fun.file, &inner.names))?;
+        }
+
+        Ok(Some((param_num, Def::Body(def))))
+    } else {
+        if !annotation.is_post {
+            return Err(LoferError::MissingDefinition {
+                loc: Location::at(&fun.file, annotation.span),
+                name: annotation.name.clone(),
+            });
+        }
+        Ok(None)
+    }
+}
+
+// converts and checks an expression in the given scope, returning it along
+// with its evaluated type
+fn infer_expr(
+    globals: &Globals,
+    file: &str,
+    body: &ast::Expr,
+    scope: &Scope,
+) -> Result<(Expr, Expr), LoferError> {
+    let (mut expr, mut spans) = convert_expr(
+        globals,
+        &scope.helpers,
+        &Context::new(&scope.names),
+        body.clone(),
+    ).map_err(|e| e.report(globals, file, &scope.names))?;
+    let mut metas = Metas::default();
+    let ctx_size = scope.types.len();
+    let mut ty = type_check_expr(
+        globals,
+        &mut metas,
+        &Context::new(&scope.types),
+        &mut expr,
+        &mut spans,
+        None,
+    ).and_then(|mut ty| {
+        metas.finish(&mut expr, ctx_size)?;
+        metas.finish(&mut ty, ctx_size)?;
+        Ok(ty)
+    }).map_err(|e| e.report(globals, file, &scope.names))?;
+    eval(&globals.defs, &mut ty, ctx_size);
+    Ok((expr, ty))
+}
+
+// the patterns that a definition binds its parameters to: the ones the user
+// wrote for explicit parameters, and the names from the annotation for any
+// implicit parameters before or between them
+fn bind_params(typ: &ast::Expr, ty: &Expr, patterns: &[ast::Pattern])
+    -> Vec<ast::Pattern>
+{
+    let ast_params = match typ {
+        ast::Expr::Arrow(arrow) => &arrow.params[..],
+        _ => &[],
+    };
+    let mut result = Vec::with_capacity(patterns.len());
+    for pattern in patterns {
+        // bind implicit parameters until we reach the next explicit one
+        while let Some(param) = ty.arrow_params.get(result.len()) {
+            if !param.implicit {
+                break;
             }
+            let name = ast_params.get(result.len()).and_then(|p| p.name.clone());
+            result.push(ast::Pattern {
+                name: name.unwrap_or_else(|| "_".into()),
+                args: Vec::new(),
+                span: pattern.span,
+            });
         }
+        result.push(pattern.clone());
+    }
+    result
+}
 
-        (
-            annotation.name.clone(),
-            definition.fname.clone(),
-            Item { ty, def: Some((param_num, def)) },
-        )
+// checks the associated items of fun, adding them to the scope of its body
+fn check_associated(
+    globals: &mut Globals,
+    fun: &ast::Item,
+    parent_name: &str,
+    inner: &mut Scope,
+) -> Result<(), LoferError> {
+    for each in &fun.associated {
+        if let Some(data) = &each.data {
+            return Err(LoferError::LocalData {
+                loc: Location::at(&each.file, data.span),
+                name: data.name.clone(),
+            });
+        }
+        let (name, short_name, item) =
+            type_check_function(globals, each, inner)?;
+        let index = globals.names.len();
+        globals.names.push(format!("{}.{}", parent_name, name));
+        globals.modules.push(globals.current_module);
+        globals.locations.push(Some(item_location(each)));
+        globals.defs.push(lift_helper(inner, item));
+        let captured = inner.types.len();
+        if short_name != name {
+            inner.helpers.push(Helper { name: short_name, index, captured });
+        }
+        inner.helpers.push(Helper { name, index, captured });
     }
+    Ok(())
+}
+
+// turns an item that was checked in the context of some parameters into
+// one that takes those parameters as arguments
+fn lift_helper(scope: &Scope, item: Item) -> Item {
+    let Item { mut ty, def } = item;
+    // the parameters are always passed explicitly by convert_expr
+    let mut arrow_params: Vec<_> = scope.types
+        .iter()
+        .zip(&scope.names)
+        .map(|(ty, name)| Param {
+            implicit: false,
+            ty: ty.clone(),
+            name: binder_name(name),
+        })
+        .collect();
+    arrow_params.append(&mut ty.arrow_params);
+    ty.arrow_params = arrow_params;
+    // definitions were already checked in the context of the parameters,
+    // which is where they now come in the arguments
+    let captured = scope.types.len();
+    let def = def.map(|(param_num, def)| (captured + param_num, def));
+    Item { ty, def }
+}
+
+fn check_annotation(
+    globals: &Globals,
+    fun: &ast::Item,
+    annotation: &ast::Annotation,
+    scope: &Scope,
+) -> Result<Expr, LoferError> {
+    check_type(globals, &fun.file, &annotation.typ, scope)
+}
+
+// converts and evaluates a type in the given scope, checking that it is one
+fn check_type(
+    globals: &Globals,
+    file: &str,
+    typ: &ast::Expr,
+    scope: &Scope,
+) -> Result<Expr, LoferError> {
+    let (mut ty, mut ty_spans) = convert_expr(
+        globals,
+        &scope.helpers,
+        &Context::new(&scope.names),
+        typ.clone()
+    ).map_err(|e| e.report(globals, file, &scope.names))?;
+    let mut metas = Metas::default();
+    sort_check_expr(
+        globals,
+        &mut metas,
+        &Context::new(&scope.types),
+        &mut ty,
+        &mut ty_spans,
+    ).and_then(|_| {
+        metas.finish(&mut ty, scope.types.len())
+    }).map_err(|e| e.report(globals, file, &scope.names))?;
+    // maybe we want to store both eval and non-eval versions?
+    eval(&globals.defs, &mut ty, scope.types.len());
+    Ok(ty)
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, PartialEq, Eq)]
 enum Ident {
     Universe(usize),
     Global(usize),
     Overload(usize),
     Local(usize),
+    Meta(usize),
+    // a hole the user wrote, until checking turns it into a meta
+    Hole(Option<Rc<str>>),
+    // literals of the primitive types
+    Nat(u64),
+    Int(i64),
+    Str(Rc<str>),
+}
+
+// the name is whatever the user called the binder, if anything, which
+// is only kept for printing
+#[derive(Clone)]
+struct Param {
+    implicit: bool,
+    ty: Expr,
+    name: Option<Rc<str>>,
+}
+
+// binders are compared by position, so names don't matter
+impl PartialEq for Param {
+    fn eq(&self, other: &Param) -> bool {
+        self.implicit == other.implicit && self.ty == other.ty
+    }
 }
 
-#[derive(Clone, PartialEq)]
+// \x1 .. xn -> (y1: A1) -> .. (ym: Am) -> head tail
+// where the lambda binders come first in the context of everything after them
+// lambda_names has what the user called each lambda binder, like Param.name
+#[derive(Clone)]
 struct Expr {
-    arrow_params: Vec<Expr>,
+    lambda_params: usize,
+    lambda_names: Vec<Option<Rc<str>>>,
+    arrow_params: Vec<Param>,
     head: Ident,
     tail: Vec<Expr>,
 }
 
+// lambda binders are compared by position too
+impl PartialEq for Expr {
+    fn eq(&self, other: &Expr) -> bool {
+        self.lambda_params == other.lambda_params
+            && self.arrow_params == other.arrow_params
+            && self.head == other.head
+            && self.tail == other.tail
+    }
+}
+
 impl Expr {
-    fn universe(l: usize) -> Self {
+    fn from_head(head: Ident) -> Self {
         Expr {
+            lambda_params: 0,
+            lambda_names: Vec::new(),
             arrow_params: Vec::new(),
-            head: Ident::Universe(l),
+            head,
             tail: Vec::new(),
         }
     }
-    fn universe_level(self: &Self) -> Option<usize> {
-        if self.arrow_params.len() > 0 || self.tail.len() > 0 {
+    fn universe(l: usize) -> Self {
+        Expr::from_head(Ident::Universe(l))
+    }
+    // U l counts too, whatever l is
+    fn is_universe(&self) -> bool {
+        self.universe_level().is_some()
+            || prims::universe_level(self).is_some()
+    }
+    fn universe_level(&self) -> Option<usize> {
+        if self.lambda_params > 0
+            || !self.arrow_params.is_empty()
+            || !self.tail.is_empty()
+        {
             None
         } else {
             match self.head {
@@ -175,59 +861,42 @@ impl Expr {
         }
     }
 
-    fn insert(self: &mut Self, mut other: Self) {
-        if (other.universe_level().is_some() || other.arrow_params.len() > 0)
-            && self.tail.len() > 0
+    // replaces the head of self with other, where ctx_size is the size of the
+    // context that the head is in, beta reducing if other is a lambda
+    // fails and leaves self as it was if other doesn't fit there, such as a
+    // type applied to arguments, which only the unchecked bodies of
+    // postulates can lead to
+    #[must_use]
+    fn insert(&mut self, mut other: Self, ctx_size: usize) -> bool {
+        if other.lambda_params > 0 && !self.tail.is_empty() {
+            let n = other.lambda_params.min(self.tail.len());
+            let args: Vec<_> = self.tail.drain(0..n).collect();
+            other.lambda_params -= n;
+            other.lambda_names.drain(0..n);
+            let fits = try_subst(&other, ctx_size, 0, &args, ctx_size)
+                .is_some_and(|reduced| self.insert(reduced, ctx_size));
+            if !fits {
+                self.tail.splice(0..0, args);
+            }
+            return fits;
+        }
+        if (other.universe_level().is_some() || !other.arrow_params.is_empty())
+            && !self.tail.is_empty()
         {
-            panic!("Substituted arrow expression into head position");
+            return false;
+        }
+        if other.lambda_params > 0 && !self.arrow_params.is_empty() {
+            return false;
         }
+        self.lambda_params += other.lambda_params;
+        self.lambda_names.append(&mut other.lambda_names);
         self.arrow_params.append(&mut other.arrow_params);
         self.head = other.head;
         // @Performance reverse all tail arrays
         // specifically to make this faster
         other.tail.append(&mut self.tail);
         self.tail = other.tail;
-    }
-
-    fn write_grouped(
-        self: &Self,
-        f: &mut std::fmt::Formatter,
-        group_algs: bool,
-    ) -> std::fmt::Result {
-        if self.arrow_params.len() > 0 || (group_algs && self.tail.len() > 0) {
-            write!(f, "({})", self)?;
-        } else {
-            write!(f, "{}", self)?;
-        }
-        Ok(())
-    }
-}
-
-impl std::fmt::Display for Expr {
-    fn fmt(self: &Self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        for ex in &self.arrow_params {
-            ex.write_grouped(f, false)?;
-            write!(f, " -> ")?;
-        }
-        match self.head {
-            Ident::Universe(l) => {
-                write!(f, "U{}", l)?;
-            },
-            Ident::Local(i) => {
-                write!(f, "x{}", i)?;
-            },
-            Ident::Global(i) => {
-                write!(f, "g{}", i)?;
-            },
-            Ident::Overload(i) => {
-                write!(f, "_g{}", i)?;
-            }
-        }
-        for ex in &self.tail {
-            write!(f, " ")?;
-            ex.write_grouped(f, true)?;
-        }
-        Ok(())
+        true
     }
 }
 
@@ -242,31 +911,31 @@ impl<'a, T> Context<'a, T> {
     fn new(this: &'a [T]) -> Self {
         Context { this, prev_size: 0, prev: None }
     }
-    fn push(self: &'a Self, next: &'a [T]) -> Self {
+    fn push(&'a self, next: &'a [T]) -> Self {
         // this doesn't actually shadow anything because of the size we use
         self.push_shadowed(next, self.size())
     }
     // shadows indeces, cannot be used with shadowed names
     // (maybe I should stop calling one of these shadowing...)
-    fn push_shadowed(self: &'a Self, next: &'a [T], unshadowed: usize) -> Self {
+    fn push_shadowed(&'a self, next: &'a [T], unshadowed: usize) -> Self {
         Context {
             prev_size: unshadowed,
             prev: Some(self),
             this: next,
         }
     }
-    fn size(self: &Self) -> usize {
+    fn size(&self) -> usize {
         self.prev_size + self.this.len()
     }
 
     // NOT valid on expressions with shadowed indeces
     // this is so that we can efficiently implement shadowed _parameter names_
-    fn index_from_value(self: &Self, name: &T) -> Option<usize>
+    fn index_from_value(&self, name: &T) -> Option<usize>
         where T: PartialEq,
     {
         let mut curr = self;
         loop {
-            if let Some(result) = get_index(&curr.this, name) {
+            if let Some(result) = get_index(curr.this, name) {
                 return Some(result + curr.prev_size);
             }
             if let Some(prev) = &curr.prev {
@@ -276,7 +945,7 @@ impl<'a, T> Context<'a, T> {
             }
         }
     }
-    fn value_from_index(self: &'a Self, index: usize) -> &'a T {
+    fn value_from_index(&'a self, index: usize) -> &'a T {
         let mut curr = self;
         loop {
             if index < curr.prev_size {
@@ -299,65 +968,434 @@ fn get_index<T: PartialEq>(names: &[T], name: &T) -> Option<usize> {
     None
 }
 
+// the spans of the ast nodes that a converted Expr came from, in the same
+// shape as the Expr itself
+#[derive(Clone)]
+struct Spans {
+    span: ast::Span,
+    arrow_params: Vec<Spans>,
+    tail: Vec<Spans>,
+}
+
+impl Spans {
+    fn leaf(span: ast::Span) -> Self {
+        Spans { span, arrow_params: Vec::new(), tail: Vec::new() }
+    }
+
+    // reshapes the spans of an expression that has had values substituted
+    // into it, so that the parts of those values point at where the
+    // variable they replaced was
+    fn fit(&mut self, expr: &Expr) {
+        let span = self.span;
+        let extra = expr.tail.len().saturating_sub(self.tail.len());
+        self.tail.splice(0..0, (0..extra).map(|_| Spans::leaf(span)));
+        while self.arrow_params.len() < expr.arrow_params.len() {
+            self.arrow_params.push(Spans::leaf(span));
+        }
+        for (spans, param) in self.arrow_params.iter_mut()
+            .zip(&expr.arrow_params)
+        {
+            spans.fit(&param.ty);
+        }
+        for (spans, ex) in self.tail.iter_mut().zip(&expr.tail) {
+            spans.fit(ex);
+        }
+    }
+}
+
 fn convert_expr(
-    globals: &Vec<String>,
-    overloads: &Vec<String>,
+    globals: &Globals,
+    helpers: &[Helper],
     locals: &Context<String>,
     mut expr: ast::Expr,
-) -> Expr {
+) -> CheckResult<(Expr, Spans)> {
+    let span = expr.span();
+    if let ast::Expr::Lambda(lambda) = expr {
+        let locals = locals.push(&lambda.params);
+        let (mut body, mut spans) =
+            convert_expr(globals, helpers, &locals, *lambda.body)?;
+        body.lambda_params += lambda.params.len();
+        let names = lambda.params.iter().map(|name| binder_name(name));
+        body.lambda_names.splice(0..0, names);
+        spans.span = span;
+        return Ok((body, spans));
+    }
+    if let ast::Expr::Literal(literal) = expr {
+        let head = match literal.value {
+            ast::Literal::Nat(n) => Ident::Nat(n),
+            ast::Literal::Int(n) => Ident::Int(n),
+            ast::Literal::Str(s) => Ident::Str(s.into()),
+        };
+        return Ok((Expr::from_head(head), Spans::leaf(span)));
+    }
+    if let ast::Expr::Hole(hole) = expr {
+        let head = Ident::Hole(hole.name.map(Rc::from));
+        return Ok((Expr::from_head(head), Spans::leaf(span)));
+    }
     let mut arrow_params = Vec::new();
+    let mut param_spans = Vec::new();
     let mut new_locals = Vec::new();
-    while let ast::Expr::Arrow(ast::ArrowExpr { params, output }) = expr {
-        for (name, ty) in params {
-            arrow_params.push(
-                convert_expr(globals, overloads, &locals.push(&new_locals), ty)
-            );
-            new_locals.push(name.unwrap_or_else(|| "_".into()));
+    while let ast::Expr::Arrow(ast::ArrowExpr { params, output, .. }) = expr {
+        for ast::Param { name, typ, implicit } in params {
+            let (ty, spans) = convert_expr(
+                globals,
+                helpers,
+                &locals.push(&new_locals),
+                typ,
+            )?;
+            let name = name.unwrap_or_else(|| "_".into());
+            let param = Param { implicit, ty, name: binder_name(&name) };
+            arrow_params.push(param);
+            param_spans.push(spans);
+            new_locals.push(name);
         }
         expr = *output;
     }
     let locals = locals.push(&new_locals);
     let alg = match expr {
-        ast::Expr::Arrow(_) => unreachable!(),
         ast::Expr::Alg(alg) => alg,
+        // arrow outputs are parsed as plain applications
+        _ => unreachable!(),
     };
 
+    let mut tail = Vec::with_capacity(alg.tail.len());
+    let mut tail_spans = Vec::with_capacity(alg.tail.len());
     let head = {
-        if let Some(id) = locals.index_from_value(&alg.head) {
+        let helper = helpers.iter().rev().find(|h| h.name == alg.head);
+        if alg.head.contains('.') {
+            resolve_qualified(globals, &alg.head, alg.span)?
+        } else if let Some(id) = locals.index_from_value(&alg.head) {
             Ident::Local(id)
-        } else if let Some(id) = get_index(globals, &alg.head) {
-            Ident::Global(id)
-        } else if let Some(id) = get_index(overloads, &alg.head) {
+        } else if let Some(helper) = helper {
+            for i in 0..helper.captured {
+                tail.push(Expr::from_head(Ident::Local(i)));
+                tail_spans.push(Spans::leaf(alg.span));
+            }
+            Ident::Global(helper.index)
+        } else if let Some(id) = get_index(&globals.names, &alg.head) {
+            // items with the same full name shadow each other, but items
+            // with other full names can still overload it
+            let short = get_index(&globals.short_names, &alg.head);
+            match short {
+                Some(s) if globals.overloads[s].iter()
+                    .any(|&o| globals.names[o] != alg.head) =>
+                {
+                    Ident::Overload(s)
+                },
+                _ => Ident::Global(id),
+            }
+        } else if let Some(id) = get_index(&globals.short_names, &alg.head) {
             Ident::Overload(id)
         } else {
+            let unknown = CheckError::new(
+                alg.span,
+                ErrorKind::UnknownIdentifier(alg.head.clone()),
+            );
             if &alg.head[..1] != "U" {
-                panic!("Could not find term for identifier: {}", alg.head);
+                return Err(unknown);
             }
-            if let Ok(l) = alg.head[1..].parse() {
-                Ident::Universe(l)
-            } else {
-                panic!("Could not find term for identifier: {}", alg.head);
+            match alg.head[1..].parse() {
+                // Uω is only ever the type of something, never written
+                Ok(prims::OMEGA) => {
+                    return Err(CheckError::new(alg.span, ErrorKind::Sort(
+                        format!("{} is too big to be a universe", alg.head)
+                    )));
+                },
+                Ok(l) => Ident::Universe(l),
+                Err(_) => return Err(unknown),
             }
         }
     };
-    let tail = alg
-        .tail
-        .into_iter()
-        .map(|ex| convert_expr(globals, overloads, &locals, ex))
+    for ex in alg.tail {
+        let (arg, spans) = convert_expr(globals, helpers, &locals, ex)?;
+        tail.push(arg);
+        tail_spans.push(spans);
+    }
+    Ok((
+        Expr {
+            lambda_params: 0,
+            lambda_names: Vec::new(),
+            arrow_params,
+            head,
+            tail,
+        },
+        Spans { span, arrow_params: param_spans, tail: tail_spans },
+    ))
+}
+
+// finds the global that module.name refers to, which is the last one with
+// that full name in the module, or else the only one with that short name
+fn resolve_qualified(globals: &Globals, qualified: &str, span: ast::Span)
+    -> CheckResult<Ident>
+{
+    let error = |kind| CheckError::new(span, kind);
+    let unknown = || error(ErrorKind::UnknownIdentifier(qualified.into()));
+    let dot = qualified.find('.').unwrap();
+    let (module, name) = (&qualified[..dot], &qualified[dot + 1..]);
+    let m = get_index(&globals.module_names, &module.to_string())
+        .ok_or_else(unknown)?;
+    let full = (0..globals.names.len())
+        .rfind(|&i| globals.modules[i] == m && globals.names[i] == name);
+    if let Some(i) = full {
+        return Ok(Ident::Global(i));
+    }
+    let short: Vec<_> = get_index(&globals.short_names, &name.to_string())
+        .map_or(&[][..], |s| &globals.overloads[s][..])
+        .iter()
+        .cloned()
+        .filter(|&i| globals.modules[i] == m)
+        .collect();
+    match short[..] {
+        [] => Err(unknown()),
+        [i] => Ok(Ident::Global(i)),
+        _ => Err(error(ErrorKind::AmbiguousOverload {
+            name: qualified.into(),
+            candidates: short,
+        })),
+    }
+}
+
+#[derive(Clone)]
+struct CheckError {
+    span: ast::Span,
+    kind: Box<ErrorKind>,
+}
+
+#[derive(Clone)]
+enum ErrorKind {
+    UnknownIdentifier(String),
+    Sort(String),
+    // expressions are kept as they are until we know what to call the locals
+    // in the ctx_size variables they were checked in
+    NotAFunction { ty: Expr, ctx_size: usize },
+    Mismatch {
+        expr: Expr,
+        actual: Expr,
+        expected: Expr,
+        difference: Option<Box<Difference>>,
+        ctx_size: usize,
+    },
+    // a lambda with no expected type, or with more parameters than it has
+    Lambda { params: usize, expected: Option<Expr>, ctx_size: usize },
+    // an implicit argument to head that nothing determined
+    UnsolvedMeta { head: Expr, ctx_size: usize },
+    // overloads that were ruled out by the shape of their type, either
+    // because their result can't take as many arguments as it was given,
+    // or because it can't be what was expected, where a result of None means
+    // it would still be a function
+    TooManyArguments { head: Expr, arity: usize, given: usize },
+    ResultMismatch {
+        head: Expr,
+        given: usize,
+        result: Option<Expr>,
+        expected: Expr,
+        ctx_size: usize,
+    },
+    // the candidates that all fit
+    AmbiguousOverload { name: String, candidates: Vec<usize> },
+    // each candidate, the argument as written that it failed at, if it was
+    // one of them, and how it failed
+    NoOverload {
+        name: String,
+        failures: Vec<(usize, Option<usize>, CheckError)>,
+    },
+    // every hole in an item, once everything else about it has checked
+    Holes(Vec<Hole>),
+}
+
+impl CheckError {
+    fn new(span: ast::Span, kind: ErrorKind) -> Self {
+        CheckError { span, kind: Box::new(kind) }
+    }
+
+    // names are the names of the locals that the checked expression could
+    // refer to
+    fn report(self, globals: &Globals, file: &str, names: &[String])
+        -> LoferError
+    {
+        let loc = Location::at(file, self.span);
+        match *self.kind {
+            ErrorKind::UnknownIdentifier(name) =>
+                LoferError::UnknownIdentifier { loc, name },
+            ErrorKind::Sort(message) =>
+                LoferError::Sort { loc, message },
+            ErrorKind::NotAFunction { ty, ctx_size } => {
+                let names = pretty::local_names(globals, names, ctx_size);
+                LoferError::Sort {
+                    loc,
+                    message: format!(
+                        "Cannot apply type family to argument(s): {}",
+                        Pretty::new(globals, &names, &ty),
+                    ),
+                }
+            },
+            ErrorKind::Mismatch {
+                expr, actual, expected, difference, ctx_size,
+            } => {
+                let difference = difference.map(|diff| {
+                    let names =
+                        pretty::local_names(globals, names, diff.ctx_size);
+                    Box::new((
+                        Pretty::new(globals, &names, &diff.left).to_string(),
+                        Pretty::new(globals, &names, &diff.right).to_string(),
+                    ))
+                });
+                let names = pretty::local_names(globals, names, ctx_size);
+                // an arrow is checked under its own parameters, but it is
+                // written outside of them
+                let outer = ctx_size.saturating_sub(expr.arrow_params.len());
+                LoferError::TypeMismatch {
+                    loc,
+                    expr: Pretty::new(globals, &names[..outer], &expr)
+                        .to_string(),
+                    actual: Pretty::new(globals, &names, &actual).to_string(),
+                    expected:
+                        Pretty::new(globals, &names, &expected).to_string(),
+                    difference,
+                }
+            },
+            ErrorKind::Lambda { params, expected, ctx_size } => {
+                let names = pretty::local_names(globals, names, ctx_size);
+                LoferError::Lambda {
+                    loc,
+                    params,
+                    expected: expected.map(|ex| {
+                        Pretty::new(globals, &names, &ex).to_string()
+                    }),
+                }
+            },
+            ErrorKind::UnsolvedMeta { head, ctx_size } => {
+                let names = pretty::local_names(globals, names, ctx_size);
+                LoferError::UnsolvedMeta {
+                    loc,
+                    name: Pretty::new(globals, &names, &head).to_string(),
+                }
+            },
+            ErrorKind::TooManyArguments { head, arity, given } => {
+                LoferError::TooManyArguments {
+                    loc,
+                    name: Pretty::new(globals, &[], &head).to_string(),
+                    arity,
+                    given,
+                }
+            },
+            ErrorKind::ResultMismatch {
+                head, given, result, expected, ctx_size,
+            } => {
+                let names = pretty::local_names(globals, names, ctx_size);
+                LoferError::ResultMismatch {
+                    loc,
+                    name: Pretty::new(globals, &[], &head).to_string(),
+                    given,
+                    result: result.map(|result| {
+                        Pretty::new(globals, &[], &result).to_string()
+                    }),
+                    expected:
+                        Pretty::new(globals, &names, &expected).to_string(),
+                }
+            },
+            ErrorKind::AmbiguousOverload { name, candidates } => {
+                let candidates = candidates.into_iter()
+                    .map(|c| candidate(globals, c, None))
+                    .collect();
+                LoferError::AmbiguousOverload { loc, name, candidates }
+            },
+            ErrorKind::NoOverload { name, failures } => {
+                let candidates = failures.into_iter()
+                    .map(|(c, arg, e)| {
+                        let message = e.report(globals, file, names).message();
+                        let failure = match arg {
+                            Some(arg) =>
+                                format!("argument {}: {}", arg + 1, message),
+                            None => message,
+                        };
+                        candidate(globals, c, Some(failure))
+                    })
+                    .collect();
+                LoferError::NoOverload { loc, name, candidates }
+            },
+            ErrorKind::Holes(holes) => {
+                let goals = holes.into_iter()
+                    .map(|hole| report_hole(globals, file, names, hole))
+                    .collect();
+                LoferError::Holes { loc, goals }
+            },
+        }
+    }
+}
+
+// the context is listed with the first local first, each with its type in
+// terms of the locals before it
+fn report_hole(globals: &Globals, file: &str, names: &[String], hole: Hole)
+    -> Goal
+{
+    let ctx_size = hole.context.len();
+    let names = pretty::local_names(globals, names, ctx_size);
+    let context = hole.context.iter()
+        .enumerate()
+        .map(|(i, ty)| {
+            let ty = Pretty::new(globals, &names[..i], ty).to_string();
+            (names[i].clone(), ty)
+        })
         .collect();
-    Expr { arrow_params, head, tail }
+    let mut ty = hole.ty;
+    eval(&globals.defs, &mut ty, ctx_size);
+    // the type was zonked once the whole item was checked, so a meta left
+    // in its place was never solved
+    let known = match ty.head {
+        Ident::Meta(_) => {
+            ty.lambda_params > 0
+                || !ty.arrow_params.is_empty()
+                || !ty.tail.is_empty()
+        },
+        _ => true,
+    };
+    Goal {
+        loc: Location::at(file, hole.span),
+        name: match hole.name {
+            Some(name) => format!("?{}", name),
+            None => "?".into(),
+        },
+        ty: if known {
+            Some(Pretty::new(globals, &names, &ty).to_string())
+        } else {
+            None
+        },
+        context,
+    }
+}
+
+// describes overload c, and why it didn't fit if it didn't
+fn candidate(globals: &Globals, c: usize, failure: Option<String>)
+    -> Candidate
+{
+    Candidate {
+        name: globals.names[c].clone(),
+        ty: Pretty::new(globals, &[], &globals.defs[c].ty).to_string(),
+        failure,
+    }
 }
 
-type CheckResult<T> = Result<T, String>;
+type CheckResult<T> = Result<T, CheckError>;
 
-// also resolves overloads, thus the mutable input
+// also resolves overloads and inserts implicit arguments, thus the mutable
+// inputs
 fn type_check_expr(
-    globals: &Vec<Item>,
-    overloads: &Vec<Vec<usize>>,
+    globals: &Globals,
+    metas: &mut Metas,
     locals: &Context<Expr>,
     expr: &mut Expr,
+    spans: &mut Spans,
     expected: Option<&Expr>,
 ) -> CheckResult<Expr> {
+    if expr.lambda_params > 0 {
+        return check_lambda(globals, metas, locals, expr, spans, expected);
+    }
+    if let Ident::Hole(_) = expr.head {
+        return Ok(check_hole(metas, locals, expr, spans.span, expected));
+    }
+    let outer_ctx_size = locals.size();
+    let overloads = &globals.overloads;
     if let Ident::Overload(i) = expr.head {
         if overloads[i].len() == 1 {
             expr.head = Ident::Global(overloads[i][0]);
@@ -366,188 +1404,614 @@ fn type_check_expr(
 
     // process variables introduced by arrow expressions
     let mut new_locals = Vec::new();
-    for each in &mut expr.arrow_params {
+    for (each, each_spans) in expr.arrow_params.iter_mut()
+        .zip(&mut spans.arrow_params)
+    {
         sort_check_expr(
             globals,
-            overloads,
+            metas,
             &locals.push(&new_locals),
-            each,
+            &mut each.ty,
+            each_spans,
         )?;
-        new_locals.push(each.clone());
+        new_locals.push(each.ty.clone());
     }
     let locals = locals.push(&new_locals);
 
-    let mut arg_actuals = Vec::with_capacity(expr.tail.len());
+    let ty = if let Ident::Overload(i) = expr.head {
+        resolve_overload(globals, metas, &locals, expr, spans, expected, i)?
+    } else {
+        check_application(globals, metas, &locals, expr, spans, None, expected)?
+    };
 
-    let overload = {
-        if let Ident::Overload(i) = expr.head {
-            Some(i)
-        } else {
-            None
+    if !expr.arrow_params.is_empty() && !ty.is_universe() {
+        return Err(CheckError::new(spans.span, ErrorKind::Sort(
+            "Expected element of a universe (in result of arrow expression)"
+            .into()
+        )));
+    }
+    if !expr.arrow_params.is_empty() && mentions_from(&ty, outer_ctx_size) {
+        return Ok(Expr::universe(prims::OMEGA));
+    }
+    Ok(ty)
+}
+
+// lambdas take the types of their parameters from the arrow expression they
+// are expected to be, also binding any implicit parameters they skip over
+fn check_lambda(
+    globals: &Globals,
+    metas: &mut Metas,
+    locals: &Context<Expr>,
+    expr: &mut Expr,
+    spans: &mut Spans,
+    expected: Option<&Expr>,
+) -> CheckResult<Expr> {
+    let params = expr.lambda_params;
+    let span = spans.span;
+    let lambda_error = |expected: Option<Expr>| CheckError::new(
+        span,
+        ErrorKind::Lambda {
+            params,
+            expected,
+            ctx_size: locals.size(),
+        },
+    );
+    let mut expected = match expected {
+        Some(expected) => expected.clone(),
+        None => return Err(lambda_error(None)),
+    };
+    metas.zonk(&mut expected, locals.size());
+    eval(&globals.defs, &mut expected, locals.size());
+
+    let mut k = 0;
+    while k < expr.lambda_params {
+        if k >= expected.arrow_params.len() {
+            return Err(lambda_error(Some(expected)));
         }
+        if expected.arrow_params[k].implicit {
+            // make room for a binder the user didn't write
+            *expr = deepen(expr, locals.size() + k, 1);
+            expr.lambda_params += 1;
+            let name = expected.arrow_params[k].name.clone();
+            expr.lambda_names.insert(k, name);
+        }
+        k += 1;
+    }
+
+    let n = expr.lambda_params;
+    let mut body_expected = expected.clone();
+    let param_types: Vec<_> = body_expected.arrow_params
+        .drain(0..n)
+        .map(|p| p.ty)
+        .collect();
+    expr.lambda_params = 0;
+    let names = mem::take(&mut expr.lambda_names);
+    let result = type_check_expr(
+        globals,
+        metas,
+        &locals.push(&param_types),
+        expr,
+        spans,
+        Some(&body_expected),
+    );
+    expr.lambda_params = n;
+    expr.lambda_names = names;
+    result?;
+    Ok(expected)
+}
+
+// holes take whatever type is expected of them, and are remembered so that
+// the type can be reported along with the locals in scope
+fn check_hole(
+    metas: &mut Metas,
+    locals: &Context<Expr>,
+    expr: &mut Expr,
+    span: ast::Span,
+    expected: Option<&Expr>,
+) -> Expr {
+    let name = match &expr.head {
+        Ident::Hole(name) => name.clone(),
+        _ => unreachable!(),
     };
-    let mut ol_solution = None;
-    let mut new_head = None;
-    let num_defs = overload.map_or(1, |i| overloads[i].len());
-    for ol_i in 0..num_defs {
-        // initialize with type of term in head position
-        let (mut actual, mut expr_ctx_size) = match expr.head {
-            Ident::Local(i) => (locals.value_from_index(i).clone(), i),
-            Ident::Global(i) => (globals[i].ty.clone(), 0),
-            Ident::Overload(i) => (globals[overloads[i][ol_i]].ty.clone(), 0),
-            Ident::Universe(l) => {
-                // we clearly aren't overloading so it's fine to short circuit
-                if expr.tail.len() > 0 {
-                    return Err("Cannot apply type to arguments".into());
-                }
-                return Ok(Expr::universe(l+1));
-            },
+    let ctx_size = locals.size();
+    let ty = match expected {
+        Some(expected) => expected.clone(),
+        None => metas.fresh(ctx_size, span, expr.head.clone()),
+    };
+    let context = (0..ctx_size)
+        .map(|i| locals.value_from_index(i).clone())
+        .collect();
+    *expr = metas.fresh(ctx_size, span, expr.head.clone());
+    metas.add_hole(Hole { span, name, ty: ty.clone(), context });
+    ty
+}
+
+// resolves the overload of short name i in head position, remembering what
+// was chosen, so that checking the same application against the same type
+// again, e.g. as an argument to each candidate of an outer overload, doesn't
+// try every candidate again
+fn resolve_overload(
+    globals: &Globals,
+    metas: &mut Metas,
+    locals: &Context<Expr>,
+    expr: &mut Expr,
+    spans: &mut Spans,
+    expected: Option<&Expr>,
+    i: usize,
+) -> CheckResult<Expr> {
+    let key = choice_key(metas, locals, expr, spans, expected, i);
+    if let Some(choice) = key.as_ref().and_then(|key| metas.choice(key)) {
+        expr.head = Ident::Global(choice?);
+        return check_application(globals, metas, locals, expr, spans, None,
+            expected);
+    }
+    let result =
+        choose_overload(globals, metas, locals, expr, spans, expected, i);
+    if let Some(key) = key {
+        let choice = match (&result, &expr.head) {
+            (Ok(_), &Ident::Global(c)) => Ok(c),
+            (Ok(_), _) => unreachable!(),
+            (Err(e), _) => Err(e.clone()),
         };
-        // check that arguments match the type expected in head position
-        let mut checked = 0;
-        let mut subbed = 0;
-        let mut valid = true;
-        while checked < expr.tail.len() {
-            if actual.arrow_params.len() == 0 {
-                // @Performance lazy eval? save the full eval for later
-                actual = subst(
-                    &actual, expr_ctx_size, 0,
-                    &expr.tail[subbed..checked], locals.size(),
-                );
-                subbed = checked;
-                expr_ctx_size = locals.size();
-                eval(globals, &mut actual, locals.size());
-                if actual.arrow_params.len() == 0 {
-                    return Err(
-                        format!("Cannot apply type family to argument(s): {}",
-                            actual)
-                    );
-                }
+        metas.add_choice(key, choice);
+    }
+    result
+}
+
+// what choosing an overload depends on, which can only be known for sure if
+// none of it depends on metas
+fn choice_key(
+    metas: &Metas,
+    locals: &Context<Expr>,
+    expr: &Expr,
+    spans: &Spans,
+    expected: Option<&Expr>,
+    i: usize,
+) -> Option<ChoiceKey> {
+    if expr.tail.iter().any(|arg| first_meta(arg).is_some()) {
+        return None;
+    }
+    let mut expected = expected.cloned();
+    if let Some(expected) = &mut expected {
+        metas.zonk(expected, locals.size());
+        if first_meta(expected).is_some() {
+            return None;
+        }
+    }
+    let mut context = Vec::with_capacity(locals.size());
+    for k in 0..locals.size() {
+        let mut ty = locals.value_from_index(k).clone();
+        metas.zonk(&mut ty, k);
+        if first_meta(&ty).is_some() {
+            return None;
+        }
+        context.push(ty);
+    }
+    Some(ChoiceKey {
+        span: spans.span,
+        overload: i,
+        args: expr.tail.clone(),
+        expected,
+        context,
+    })
+}
+
+// tries the overloads of short name i that could fit, keeping the only one
+// that does
+fn choose_overload(
+    globals: &Globals,
+    metas: &mut Metas,
+    locals: &Context<Expr>,
+    expr: &mut Expr,
+    spans: &mut Spans,
+    expected: Option<&Expr>,
+    i: usize,
+) -> CheckResult<Expr> {
+    let mut goal = expected.cloned();
+    if let Some(goal) = &mut goal {
+        metas.zonk(goal, locals.size());
+        eval(&globals.defs, goal, locals.size());
+    }
+    let mut candidates = Vec::new();
+    let mut failures = Vec::new();
+    for &candidate in &globals.overloads[i] {
+        // shadowed by a later item with the same full name
+        let name = &globals.names[candidate];
+        if get_index(&globals.names, name) != Some(candidate) {
+            continue;
+        }
+        let fits = could_fit(globals, candidate, expr.tail.len(), goal.as_ref(),
+            locals.size(), spans.span);
+        match fits {
+            Ok(()) => candidates.push(candidate),
+            Err(e) => failures.push((candidate, None, e)),
+        }
+    }
+
+    // once only one candidate is left, its arguments can just be checked
+    // against the types it expects of them
+    let arg_actuals = if candidates.len() > 1 {
+        Some(infer_args(globals, metas, locals, expr, spans))
+    } else {
+        None
+    };
+
+    let before = metas.save();
+    let mut solutions = Vec::new();
+    for candidate in candidates {
+        let mut attempt = expr.clone();
+        let mut attempt_spans = spans.clone();
+        attempt.head = Ident::Global(candidate);
+        let result = check_application(
+            globals,
+            metas,
+            locals,
+            &mut attempt,
+            &mut attempt_spans,
+            arg_actuals.as_deref(),
+            expected,
+        );
+        match result {
+            Ok(ty) => solutions.push(
+                (candidate, attempt, attempt_spans, ty, metas.save())
+            ),
+            Err(e) => {
+                // the argument as written that the error is inside of
+                let arg = spans.tail.iter().position(|arg| {
+                    arg.span.start <= e.span.start && e.span.end <= arg.span.end
+                });
+                failures.push((candidate, arg, e));
+            },
+        }
+        metas.restore(&before);
+    }
+
+    let name = globals.short_names[i].clone();
+    // in the order that they were declared, whether they were ruled out
+    // before checking or not
+    failures.sort_by_key(|failure| failure.0);
+    if solutions.len() > 1 {
+        let candidates = solutions.iter().map(|solution| solution.0).collect();
+        return Err(CheckError::new(
+            spans.span,
+            ErrorKind::AmbiguousOverload { name, candidates },
+        ));
+    }
+    if let Some((_, attempt, attempt_spans, ty, solved)) = solutions.pop() {
+        *expr = attempt;
+        *spans = attempt_spans;
+        metas.restore(&solved);
+        Ok(ty)
+    } else {
+        Err(CheckError::new(
+            spans.span,
+            ErrorKind::NoOverload { name, failures },
+        ))
+    }
+}
+
+// the types of the arguments of an overloaded application, as far as they
+// can be found before knowing which overload it is
+// the ones that can't be, like lambdas, overloads that need to know their
+// type, or anything ill typed, are left to check against the type that each
+// candidate expects instead
+fn infer_args(
+    globals: &Globals,
+    metas: &mut Metas,
+    locals: &Context<Expr>,
+    expr: &mut Expr,
+    spans: &mut Spans,
+) -> Vec<Option<Expr>> {
+    let mut arg_actuals = Vec::with_capacity(expr.tail.len());
+    for (arg, arg_spans) in expr.tail.iter_mut().zip(&mut spans.tail) {
+        if arg.lambda_params > 0 {
+            arg_actuals.push(None);
+            continue;
+        }
+        let before = (arg.clone(), arg_spans.clone(), metas.save());
+        match type_check_expr(globals, metas, locals, arg, arg_spans, None) {
+            Ok(ty) => arg_actuals.push(Some(ty)),
+            Err(_) => {
+                *arg = before.0;
+                *arg_spans = before.1;
+                metas.restore(&before.2);
+                arg_actuals.push(None);
+            },
+        }
+    }
+    arg_actuals
+}
+
+// whether global c could be given the number of explicit arguments and have
+// the type expected of it, going only by the shape of its type
+fn could_fit(
+    globals: &Globals,
+    c: usize,
+    given: usize,
+    expected: Option<&Expr>,
+    ctx_size: usize,
+    span: ast::Span,
+) -> CheckResult<()> {
+    let ty = &globals.defs[c].ty;
+    let arity = ty.arrow_params.iter().filter(|p| !p.implicit).count();
+    let output = Expr {
+        lambda_params: 0,
+        lambda_names: Vec::new(),
+        arrow_params: Vec::new(),
+        head: ty.head.clone(),
+        tail: ty.tail.clone(),
+    };
+    if given > arity && is_rigid(globals, &output) {
+        return Err(CheckError::new(span, ErrorKind::TooManyArguments {
+            head: Expr::from_head(Ident::Global(c)),
+            arity,
+            given,
+        }));
+    }
+    let expected = match expected {
+        Some(expected) if expected.arrow_params.is_empty()
+            && is_rigid(globals, expected) => expected,
+        _ => return Ok(()),
+    };
+    let result = if given < arity {
+        // still a function
+        None
+    } else if is_rigid(globals, &output)
+        && !(output.is_universe() && expected.is_universe())
+        && output.head != expected.head
+    {
+        Some(Expr::from_head(output.head))
+    } else {
+        return Ok(());
+    };
+    Err(CheckError::new(span, ErrorKind::ResultMismatch {
+        head: Expr::from_head(Ident::Global(c)),
+        given,
+        result,
+        expected: expected.clone(),
+        ctx_size,
+    }))
+}
+
+// whether a type is of a form that nothing it could be applied to or
+// evaluate to would change, like a universe or a data type
+fn is_rigid(globals: &Globals, ty: &Expr) -> bool {
+    if ty.lambda_params > 0 || !ty.arrow_params.is_empty() {
+        return false;
+    }
+    match ty.head {
+        Ident::Universe(_) => true,
+        Ident::Global(g) => globals.defs[g].def.is_none() || ty.is_universe(),
+        _ => false,
+    }
+}
+
+// checks the arguments in the tail of expr against the type of its head,
+// inserting metas wherever the head takes an implicit argument
+// if arg_actuals is given then the explicit arguments have already been
+// checked and these are their types, except for lambdas which still need
+// checking
+fn check_application(
+    globals: &Globals,
+    metas: &mut Metas,
+    locals: &Context<Expr>,
+    expr: &mut Expr,
+    spans: &mut Spans,
+    arg_actuals: Option<&[Option<Expr>]>,
+    expected: Option<&Expr>,
+) -> CheckResult<Expr> {
+    // unsigned literals are Ints too, wherever an Int is expected
+    if let Ident::Nat(n) = expr.head {
+        let int = Expr::from_head(Ident::Global(prims::INT));
+        if expected == Some(&int) && n <= i64::MAX as u64 {
+            expr.head = Ident::Int(n as i64);
+        }
+    }
+    // so are levels
+    let level = Expr::from_head(Ident::Global(prims::LEVEL));
+    // initialize with type of term in head position
+    let (mut actual, mut expr_ctx_size) = match expr.head {
+        Ident::Local(i) => (locals.value_from_index(i).clone(), i),
+        Ident::Global(i) => (globals.defs[i].ty.clone(), 0),
+        Ident::Universe(l) => {
+            if !expr.tail.is_empty() {
+                return Err(CheckError::new(spans.span, ErrorKind::Sort(
+                    "Cannot apply type to arguments".into()
+                )));
+            }
+            let actual = if globals.options.type_in_type {
+                Expr::universe(l)
+            } else if l < prims::OMEGA - 1 {
+                Expr::universe(l + 1)
+            } else {
+                // anything bigger would have to be in Uω, which has no type
+                return Err(CheckError::new(spans.span, ErrorKind::Sort(
+                    "Universe is too big to have a type".into()
+                )));
+            };
+            if let Some(expected) = expected {
+                assert_type(globals, metas, locals, expr, spans, &actual,
+                    expected)?;
             }
-            // we want to check that the arguments have the type they are meant to
-            // have, i.e. expected is the thing that head takes, actual is the
-            // thing in tail
-            let arg_expected_base = actual.arrow_params.remove(0);
-
-            // @Memory maybe subst could take &mut param?
-            // @Performance skip this cloning operation if i is 0?
-            let mut arg_expected = subst(
-                &arg_expected_base, expr_ctx_size, 0,
+            return Ok(actual);
+        },
+        Ident::Nat(_) if expected == Some(&level) => (level.clone(), 0),
+        Ident::Nat(_) => (Expr::from_head(Ident::Global(prims::NAT)), 0),
+        Ident::Int(_) => (Expr::from_head(Ident::Global(prims::INT)), 0),
+        Ident::Str(_) => (Expr::from_head(Ident::Global(prims::STRING)), 0),
+        Ident::Overload(_) | Ident::Meta(_) | Ident::Hole(_) => {
+            unreachable!("overloads are resolved before checking arguments, \
+                holes never have any, and metas are only inserted \
+                afterwards");
+        },
+    };
+    // solutions can mention the parameters we are about to substitute
+    metas.zonk(&mut actual, expr_ctx_size);
+    // implicit arguments the expected type would take itself aren't inserted
+    // at the end
+    let expects_implicit = expected.is_some_and(|ex| {
+        !ex.arrow_params.is_empty() && ex.arrow_params[0].implicit
+    });
+    // check that arguments match the type expected in head position
+    let mut checked = 0;
+    let mut subbed = 0;
+    let mut explicit = 0;
+    let mut implicit_types = Vec::new();
+    loop {
+        if actual.arrow_params.is_empty() {
+            // @Performance lazy eval? save the full eval for later
+            actual = subst(
+                &actual, expr_ctx_size, 0,
                 &expr.tail[subbed..checked], locals.size(),
             );
-            // @Performance that's a lot of eval
-            eval(globals, &mut arg_expected, locals.size());
-            // @Simplicity so many branches and things...
-            // the alternative is some code duplication, or just recalculate
-            // types every time, always using goals even from overloads (NP)
-            if checked >= arg_actuals.len() {
-                let maybe_arg_expected = {
-                    if overload.is_some() {
-                        None
-                    } else {
-                        Some(&arg_expected)
-                    }
-                };
-                // @Performance we don't actually use arg_actuals most of the
-                // time?
-                arg_actuals.push(type_check_expr(
-                    globals,
-                    overloads,
-                    &locals,
-                    &mut expr.tail[checked],
-                    maybe_arg_expected,
-                )?);
-            }
-            if overload.is_some() {
-                let result = assert_type(
-                    &expr.tail[checked],
-                    &arg_actuals[checked],
-                    &arg_expected,
-                );
-                if result.is_err() {
-                    valid = false;
-                    break;
+            subbed = checked;
+            expr_ctx_size = locals.size();
+            metas.zonk(&mut actual, locals.size());
+            eval(&globals.defs, &mut actual, locals.size());
+            if actual.arrow_params.is_empty() {
+                if checked < expr.tail.len() {
+                    return Err(CheckError::new(
+                        spans.span,
+                        ErrorKind::NotAFunction {
+                            ty: actual,
+                            ctx_size: locals.size(),
+                        },
+                    ));
                 }
+                break;
             }
-            checked += 1;
         }
+        let implicit = actual.arrow_params[0].implicit;
+        if checked == expr.tail.len() && (!implicit || expects_implicit) {
+            break;
+        }
+        // we want to check that the arguments have the type they are meant to
+        // have, i.e. expected is the thing that head takes, actual is the
+        // thing in tail
+        let arg_expected_base = actual.arrow_params.remove(0).ty;
 
-        // check/return result of applying head to all given arguments
-        let mut actual = subst(
-            &actual, expr_ctx_size, 0,
+        // @Memory maybe subst could take &mut param?
+        // @Performance skip this cloning operation if i is 0?
+        let mut arg_expected = subst(
+            &arg_expected_base, expr_ctx_size, 0,
             &expr.tail[subbed..checked], locals.size(),
         );
-        eval(globals, &mut actual, locals.size());
-
-        if let Some(expected) = expected {
-            let result = assert_type(
-                expr,
-                &actual,
-                expected,
-            );
-            if overload.is_none() {
-                result?;
-            } else if result.is_err() {
-                valid = false;
-            }
-        }
-        if valid {
-            if ol_solution.is_some() {
-                return Err("multiple valid overloads".into());
-            }
-            ol_solution = Some(actual);
-            if let Some(i) = overload {
-                new_head = Some(Ident::Global(overloads[i][ol_i]));
-            }
+        // @Performance that's a lot of eval
+        metas.zonk(&mut arg_expected, locals.size());
+        eval(&globals.defs, &mut arg_expected, locals.size());
+        if implicit {
+            let head = expr.head.clone();
+            let meta = metas.fresh(locals.size(), spans.span, head);
+            expr.tail.insert(checked, meta);
+            implicit_types.push((checked, arg_expected));
+            spans.tail.insert(checked, Spans::leaf(spans.span));
+        } else if let Some(Some(arg_actual)) = arg_actuals.map(|a| &a[explicit]) {
+            assert_type(
+                globals,
+                metas,
+                locals,
+                &expr.tail[checked],
+                &spans.tail[checked],
+                arg_actual,
+                &arg_expected,
+            )?;
+            explicit += 1;
+        } else {
+            type_check_expr(
+                globals,
+                metas,
+                locals,
+                &mut expr.tail[checked],
+                &mut spans.tail[checked],
+                Some(&arg_expected),
+            )?;
+            explicit += 1;
         }
+        checked += 1;
     }
 
-    if let Some(h) = new_head {
-        expr.head = h;
-    }
+    // check/return result of applying head to all given arguments
+    let mut actual = subst(
+        &actual, expr_ctx_size, 0,
+        &expr.tail[subbed..checked], locals.size(),
+    );
+    metas.zonk(&mut actual, locals.size());
+    eval(&globals.defs, &mut actual, locals.size());
 
-    if ol_solution.is_none() {
-        if overload.is_some() {
-            return Err("no valid overloads".into());
-        } else {
-            // non-overload errors short circuit so we can't get here
-            unreachable!();
-        }
+    if let Some(expected) = expected {
+        assert_type(globals, metas, locals, expr, spans, &actual, expected)?;
     }
-    let ty = ol_solution.unwrap();
 
-    if expr.arrow_params.len() > 0 && ty.universe_level().is_none() {
-        return Err(
-            "Expected element of a universe (in result of arrow expression)"
-            .into()
-        );
+    // the type of an implicit argument can mention earlier ones that nothing
+    // else determines, like l in `{l: Level} -> {A: U l} -> A -> A`, so
+    // those are found from the type of whatever solved it
+    for (k, mut ty) in implicit_types {
+        metas.zonk(&mut ty, locals.size());
+        let mut arg = expr.tail[k].clone();
+        metas.zonk(&mut arg, locals.size());
+        if first_meta(&ty).is_none() || first_meta(&arg).is_some() {
+            continue;
+        }
+        let mut arg_spans = Spans::leaf(spans.tail[k].span);
+        arg_spans.fit(&arg);
+        type_check_expr(globals, metas, locals, &mut arg, &mut arg_spans,
+            Some(&ty))?;
     }
-    return Ok(ty);
+    Ok(actual)
 }
 
+// checks that expr is a type, returning the universe it is in, which could
+// be U l for a level l that depends on the context
 fn sort_check_expr(
-    globals: &Vec<Item>,
-    overloads: &Vec<Vec<usize>>,
+    globals: &Globals,
+    metas: &mut Metas,
     locals: &Context<Expr>,
     expr: &mut Expr,
-) -> CheckResult<usize> {
+    spans: &mut Spans,
+) -> CheckResult<Expr> {
     // we could start using "Sort" as a goal or something, but it would be
     // strange to encourage types and terms to have overloaded names...
-    let actual = type_check_expr(globals, overloads, locals, expr, None)?;
-    if let Some(l) = actual.universe_level() {
-        Ok(l)
+    let mut actual = type_check_expr(globals, metas, locals, expr, spans, None)?;
+    metas.zonk(&mut actual, locals.size());
+    if actual.is_universe() {
+        Ok(actual)
     } else {
-        return Err("Expected element of a universe".into());
+        Err(CheckError::new(
+            spans.span,
+            ErrorKind::Sort("Expected element of a universe".into()),
+        ))
     }
 }
 
-fn assert_type(expr: &Expr, actual: &Expr, expected: &Expr) -> CheckResult<()> {
-    if actual != expected {
-        return Err(
-            format!(
-                "{} has type:\n  {}\n\nbut it was expected to have type:\n  {}",
-               expr, actual, expected)
-        );
+fn assert_type(
+    globals: &Globals,
+    metas: &mut Metas,
+    locals: &Context<Expr>,
+    expr: &Expr,
+    spans: &Spans,
+    actual: &Expr,
+    expected: &Expr,
+) -> CheckResult<()> {
+    if let Err(mut difference) =
+        subtype(globals, metas, locals.size(), actual, expected)
+    {
+        let mut expr = expr.clone();
+        let mut actual = actual.clone();
+        let mut expected = expected.clone();
+        metas.zonk(&mut expr, locals.size());
+        metas.zonk(&mut actual, locals.size());
+        metas.zonk(&mut expected, locals.size());
+        metas.zonk(&mut difference.left, difference.ctx_size);
+        metas.zonk(&mut difference.right, difference.ctx_size);
+        // only worth pointing out if it isn't the whole thing
+        let whole = difference.left == actual && difference.right == expected;
+        return Err(CheckError::new(spans.span, ErrorKind::Mismatch {
+            expr,
+            actual,
+            expected,
+            difference: if whole { None } else { Some(difference) },
+            ctx_size: locals.size(),
+        }));
     }
     Ok(())
 }
@@ -561,32 +2025,110 @@ fn eval_on(globals: &Vec<Item>, xs: &mut Vec<Expr>, ctx_size: &mut usize, incr:
     }
 }
 
-fn eval(globals: &Vec<Item>, expr: &mut Expr, mut ctx_size: usize) {
-    eval_on(globals, &mut expr.arrow_params, &mut ctx_size, true);
+fn eval_params(globals: &Vec<Item>, xs: &mut Vec<Param>, ctx_size: &mut usize) {
+    for x in xs {
+        eval(globals, &mut x.ty, *ctx_size);
+        *ctx_size += 1;
+    }
+}
+
+fn eval(globals: &Vec<Item>, expr: &mut Expr, outer_ctx_size: usize) {
+    let mut ctx_size = outer_ctx_size + expr.lambda_params;
+    eval_params(globals, &mut expr.arrow_params, &mut ctx_size);
     eval_on(globals, &mut expr.tail, &mut ctx_size, false);
 
     while let Ident::Global(i) = expr.head {
-        if globals[i].def.is_none() {
-            break;
+        let (param_num, def) = match &globals[i].def {
+            Some((param_num, def)) if expr.tail.len() >= *param_num =>
+                (*param_num, def),
+            _ => break,
+        };
+
+        let args = expr.tail[0..param_num].to_vec();
+        let mut result = match unfold(def, args, ctx_size) {
+            Some(result) => result,
+            // stuck until we know which constructor it is
+            None => break,
+        };
+        if let Def::Body(_) = def {
+            // a definition by cases only unfolds once its cases compute
+            result = match eliminate(globals, result, ctx_size) {
+                Some(result) => result,
+                None => break,
+            };
         }
-        let &(param_num, ref def) = globals[i].def.as_ref().unwrap();
-        if expr.tail.len() < param_num {
-            break;
+        let applied: Vec<_> = expr.tail.drain(0..param_num).collect();
+        if result.lambda_params > 0 {
+            // the lambda may get applied to the rest of the tail, so start
+            // again with whatever that reduces to
+            if !expr.insert(result, ctx_size) {
+                // an ill-typed postulate, which stays stuck instead
+                expr.tail.splice(0..0, applied);
+                return;
+            }
+            return eval(globals, expr, outer_ctx_size);
         }
-
-        let mut result = subst(
-            &def, 0, 0,
-            &expr.tail[0..param_num], ctx_size,
-        );
         // recurse... often redundant... @Performance? combine with subst?
         // type checking should prevent associativity problems
         // A -> (B -> x y z) w
-        eval_on(globals, &mut result.arrow_params, &mut ctx_size, true);
+        eval_params(globals, &mut result.arrow_params, &mut ctx_size);
         eval_on(globals, &mut result.tail, &mut ctx_size, false);
         // @Performance we are allocating again every time...
         // could just combine these steps or something more tricky
+        if !expr.insert(result, ctx_size) {
+            expr.tail.splice(0..0, applied);
+            break;
+        }
+    }
+}
+
+// steps through the eliminators that expr starts with, as long as they are
+// applied to constructors, giving None once one of them is stuck
+fn eliminate(globals: &Vec<Item>, mut expr: Expr, ctx_size: usize)
+    -> Option<Expr>
+{
+    while let Ident::Global(i) = expr.head {
+        let (param_num, major) = match &globals[i].def {
+            Some((param_num, Def::Cases { major, .. }))
+                if expr.tail.len() >= *param_num => (*param_num, *major),
+            _ => break,
+        };
+        eval(globals, &mut expr.tail[major], ctx_size);
+        let def = &globals[i].def.as_ref().unwrap().1;
+        let args = expr.tail[0..param_num].to_vec();
+        let result = unfold(def, args, ctx_size)?;
         expr.tail.drain(0..param_num);
-        expr.insert(result);
+        if !expr.insert(result, ctx_size) {
+            return None;
+        }
+    }
+    Some(expr)
+}
+
+// the body of a definition applied to args, if the constructors they
+// are applications of decide which case of it applies
+fn unfold(mut def: &Def, mut args: Vec<Expr>, ctx_size: usize) -> Option<Expr> {
+    loop {
+        match def {
+            Def::Body(body) => return try_subst(body, 0, 0, &args, ctx_size),
+            Def::Prim(prim) => return prims::apply(*prim, &args, ctx_size),
+            Def::Cases { major, params, cases } => {
+                let scrutinee = args[*major].clone();
+                if scrutinee.lambda_params > 0
+                    || !scrutinee.arrow_params.is_empty()
+                {
+                    return None;
+                }
+                let case = cases.iter().find(|&&(c, _)| {
+                    scrutinee.head == Ident::Global(c)
+                })?;
+                def = &case.1;
+                args.splice(
+                    *major..*major + 1,
+                    scrutinee.tail.into_iter().skip(*params),
+                );
+            },
+        }
     }
 }
 
@@ -596,22 +2138,38 @@ fn eval(globals: &Vec<Item>, expr: &mut Expr, mut ctx_size: usize) {
 // but with Xi[x(n+i) <- x(n+e+i)] in each substitution,
 // in cases where arrow expressions are substituted _into_ arrow expressions
 fn subst(
-    base: &Expr, shared_ctx_size: usize, mut extra_ctx_size: usize,
+    base: &Expr, shared_ctx_size: usize, extra_ctx_size: usize,
     args: &[Expr], arg_ctx_size: usize,
 ) -> Expr {
+    try_subst(base, shared_ctx_size, extra_ctx_size, args, arg_ctx_size)
+        .expect("substituted an argument where it doesn't fit")
+}
+
+// subst, but failing if an argument doesn't fit where it is substituted,
+// which only happens with the unchecked bodies of postulates
+fn try_subst(
+    base: &Expr, shared_ctx_size: usize, mut extra_ctx_size: usize,
+    args: &[Expr], arg_ctx_size: usize,
+) -> Option<Expr> {
     // just a dumb default... we overwrite everything
     let mut result = Expr::universe(0);
+    result.lambda_params = base.lambda_params;
+    result.lambda_names = base.lambda_names.clone();
+    extra_ctx_size += base.lambda_params;
     result.arrow_params = Vec::with_capacity(base.arrow_params.len());
-    for ex in &base.arrow_params {
-        result.arrow_params.push(
-             subst(ex, shared_ctx_size, extra_ctx_size, args, arg_ctx_size)
-        );
+    for param in &base.arrow_params {
+        result.arrow_params.push(Param {
+            implicit: param.implicit,
+            ty: try_subst(&param.ty, shared_ctx_size, extra_ctx_size,
+                args, arg_ctx_size)?,
+            name: param.name.clone(),
+        });
         extra_ctx_size += 1;
     }
     result.tail = Vec::with_capacity(base.tail.len());
     for ex in &base.tail {
         result.tail.push(
-             subst(ex, shared_ctx_size, extra_ctx_size, args, arg_ctx_size)
+             try_subst(ex, shared_ctx_size, extra_ctx_size, args, arg_ctx_size)?
         );
     }
     match base.head {
@@ -624,23 +2182,37 @@ fn subst(
                     arg_ctx_size,
                     extra_ctx_size,
                 );
-                result.insert(arg);
+                if !result.insert(arg, arg_ctx_size + extra_ctx_size) {
+                    return None;
+                }
             } else {
                 let e = i - (shared_ctx_size + args.len());
                 result.head = Ident::Local(arg_ctx_size + e);
             }
         },
-        _ => result.head = base.head,
+        _ => result.head = base.head.clone(),
     }
-    result
+    Some(result)
+}
+
+// whether expr refers to any local at level from or above
+fn mentions_from(expr: &Expr, from: usize) -> bool {
+    match expr.head {
+        Ident::Local(i) if i >= from => return true,
+        _ => (),
+    }
+    expr.arrow_params.iter().any(|param| mentions_from(&param.ty, from))
+        || expr.tail.iter().any(|ex| mentions_from(ex, from))
 }
 
 fn deepen(arg: &Expr, arg_ctx_size: usize, extra: usize) -> Expr {
     let mut arrow_params = Vec::with_capacity(arg.arrow_params.len());
-    for ex in &arg.arrow_params {
-        arrow_params.push(
-            deepen(ex, arg_ctx_size, extra)
-        );
+    for param in &arg.arrow_params {
+        arrow_params.push(Param {
+            implicit: param.implicit,
+            ty: deepen(&param.ty, arg_ctx_size, extra),
+            name: param.name.clone(),
+        });
     }
     let mut tail = Vec::with_capacity(arg.tail.len());
     for ex in &arg.tail {
@@ -648,12 +2220,18 @@ fn deepen(arg: &Expr, arg_ctx_size: usize, extra: usize) -> Expr {
             deepen(ex, arg_ctx_size, extra)
         );
     }
-    let mut head = arg.head;
+    let mut head = arg.head.clone();
     if let Ident::Local(i) = &mut head {
         if *i >= arg_ctx_size {
             *i += extra;
         }
     }
-    Expr { arrow_params, head, tail }
+    Expr {
+        lambda_params: arg.lambda_params,
+        lambda_names: arg.lambda_names.clone(),
+        arrow_params,
+        head,
+        tail,
+    }
 }
 
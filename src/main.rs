@@ -1,18 +1,6 @@
 extern crate lofer_lang;
 
-use std::fs::File;
-use std::io::prelude::*;
-
-fn read_code(parser: &lofer_lang::ProgramParser, path: &str)
-    -> Vec<lofer_lang::ast::Item>
-{
-    let mut file = File::open(path).expect("Failed to open file");
-    let mut contents = String::new();
-    file.read_to_string(&mut contents).expect("Failed to read file");
-
-    let programs = parser.parse(&contents);
-    programs
-}
+use lofer_lang::LoferError;
 
 fn main() {
     let mut args = ::std::env::args();
@@ -20,17 +8,119 @@ fn main() {
 
     let parser = lofer_lang::ProgramParser::new();
     let mut globals = lofer_lang::Globals::new();
+    let mut modules = lofer_lang::Modules::new();
 
-    for path in args {
-        let program = read_code(&parser, &path);
+    let mut keep_going = false;
+    let mut terms = Vec::new();
+    let mut run = false;
+    let mut repl = false;
+    let mut files = Vec::new();
+    let mut errors: Vec<LoferError> = Vec::new();
+    while let Some(arg) = args.next() {
+        if arg == "--keep-going" || arg == "-k" {
+            keep_going = true;
+            continue;
+        }
+        if arg == "--cumulative" {
+            globals.options_mut().cumulative = true;
+            continue;
+        }
+        if arg == "--type-in-type" {
+            globals.options_mut().type_in_type = true;
+            continue;
+        }
+        if arg == "--repl" || arg == "-i" {
+            repl = true;
+            continue;
+        }
+        if arg == "--run" || arg == "-r" {
+            run = true;
+            continue;
+        }
+        if arg == "--path" || arg == "-I" {
+            match args.next() {
+                Some(dir) => modules.add_search_dir(&dir),
+                None => {
+                    eprintln!("{} needs a directory to search", arg);
+                    ::std::process::exit(1);
+                },
+            }
+            continue;
+        }
+        if arg == "--cache" {
+            match args.next() {
+                Some(dir) => modules.set_cache_dir(&dir),
+                None => {
+                    eprintln!("{} needs a directory to save checked files in",
+                        arg);
+                    ::std::process::exit(1);
+                },
+            }
+            continue;
+        }
+        if arg == "--eval" || arg == "-e" {
+            match args.next() {
+                Some(term) => terms.push(term),
+                None => {
+                    eprintln!("{} needs a term to evaluate", arg);
+                    ::std::process::exit(1);
+                },
+            }
+            continue;
+        }
+        files.push(arg);
+    }
 
-        println!("Type checking {}", path);
+    // every flag applies to every file, wherever it was given
+    for path in &files {
+        let load_errors =
+            modules.load(&parser, &mut globals, path, keep_going);
+        if keep_going {
+            errors.extend(load_errors);
+        } else if let Some(e) = load_errors.first() {
+            eprintln!("{}", e);
+            // the repl can still be used to fix the file and reload it
+            if !repl {
+                ::std::process::exit(1);
+            }
+        }
+    }
+    if !errors.is_empty() {
+        for e in &errors {
+            eprintln!("{}\n", e);
+        }
+        eprintln!("{} error(s)", errors.len());
+        if !repl {
+            ::std::process::exit(1);
+        }
+    }
 
-        lofer_lang::type_check_all(&mut globals, program);
+    for term in &terms {
+        // a lone name is looked up directly, anything else is checked first
+        let is_name = term.chars()
+            .all(|c| c.is_alphanumeric() || "_'".contains(c));
+        let result = if is_name {
+            lofer_lang::evaluate(&globals, term)
+        } else {
+            lofer_lang::evaluate_expr(&globals, term)
+        };
+        match result {
+            Ok(value) => println!("{} = {}", term, value),
+            Err(e) => {
+                eprintln!("{}", e);
+                ::std::process::exit(1);
+            },
+        }
     }
-    //let expr = conversion::convert(programses);
 
-    //let result = expr.reduce();
+    if run {
+        if let Err(e) = lofer_lang::run(&globals, "main") {
+            eprintln!("{}", e);
+            ::std::process::exit(1);
+        }
+    }
 
-    //println!("Result:\n  {:?}", result);
+    if repl {
+        lofer_lang::repl(&parser, &mut modules, &files, &mut globals);
+    }
 }
@@ -0,0 +1,294 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use ast;
+use cache;
+use error::{LoferError, Location};
+use indent_parser::ProgramParser;
+
+use super::{Globals, check_items, print_global};
+
+// finds and checks the files that programs import, so that each one is
+// checked once, before anything that imports it
+pub struct Modules {
+    // where else to look for imports, after the importing file's directory
+    search_path: Vec<PathBuf>,
+    // canonical paths of the files that have been checked
+    loaded: Vec<PathBuf>,
+    // the files being checked, each imported by the one before it, as their
+    // canonical paths and the paths they were found at
+    loading: Vec<(PathBuf, String)>,
+    // where the globals of checked files are saved, to be loaded instead of
+    // checking them again
+    cache: Option<PathBuf>,
+    // false once globals have changed other than by checking a file, like
+    // when something failed to check, after which they won't match what any
+    // later entry was saved after
+    caching: bool,
+    // the key of the last file loaded, which the next one's key includes
+    state: u64,
+    // whether to keep from printing what gets checked
+    quiet: bool,
+}
+
+impl Default for Modules {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Modules {
+    pub fn new() -> Self {
+        Modules {
+            search_path: Vec::new(),
+            loaded: Vec::new(),
+            loading: Vec::new(),
+            cache: None,
+            caching: true,
+            state: 0,
+            quiet: false,
+        }
+    }
+
+    pub fn add_search_dir(&mut self, dir: &str) {
+        self.search_path.push(dir.into());
+    }
+
+    pub fn set_cache_dir(&mut self, dir: &str) {
+        self.cache = Some(dir.into());
+    }
+
+    pub fn set_quiet(&mut self) {
+        self.quiet = true;
+    }
+
+    // for when globals are changed by something other than loading a file
+    pub fn stop_caching(&mut self) {
+        self.caching = false;
+    }
+
+    // forgets every file that was loaded, for loading them again into new
+    // globals
+    pub fn forget(&mut self) {
+        self.loaded.clear();
+        self.caching = true;
+        self.state = 0;
+    }
+
+    // checks the file at path after everything it imports, unless it has
+    // been checked already
+    // with keep_going every error is returned, otherwise only the first
+    pub fn load(
+        &mut self,
+        parser: &ProgramParser,
+        globals: &mut Globals,
+        path: &str,
+        keep_going: bool,
+    ) -> Vec<LoferError> {
+        let loc = Location { file: path.into(), line: 1, column: 1 };
+        self.load_from(parser, globals, path, loc, keep_going)
+    }
+
+    // like load, but with the contents of the file given, e.g. by an editor
+    // that hasn't saved them yet
+    pub fn load_source(
+        &mut self,
+        parser: &ProgramParser,
+        globals: &mut Globals,
+        path: &str,
+        contents: &str,
+        keep_going: bool,
+    ) -> Vec<LoferError> {
+        let canonical = fs::canonicalize(path)
+            .unwrap_or_else(|_| PathBuf::from(path));
+        self.check_source(parser, globals, path, canonical, contents,
+            keep_going)
+    }
+
+    fn load_from(
+        &mut self,
+        parser: &ProgramParser,
+        globals: &mut Globals,
+        path: &str,
+        loc: Location,
+        keep_going: bool,
+    ) -> Vec<LoferError> {
+        let read_error = |loc: Location, e: io::Error| LoferError::ReadFile {
+            loc,
+            message: format!("{}: {}", path, e),
+        };
+        let canonical = match fs::canonicalize(path) {
+            Ok(canonical) => canonical,
+            Err(e) => return vec![read_error(loc, e)],
+        };
+        if self.loaded.contains(&canonical) {
+            return Vec::new();
+        }
+        if let Some(start) = self.loading.iter()
+            .position(|(each, _)| *each == canonical)
+        {
+            let mut cycle: Vec<_> = self.loading[start..].iter()
+                .map(|(_, name)| name.clone())
+                .collect();
+            cycle.push(path.into());
+            return vec![LoferError::ImportCycle { loc, cycle }];
+        }
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) => return vec![read_error(loc, e)],
+        };
+        self.check_source(parser, globals, path, canonical, &contents,
+            keep_going)
+    }
+
+    // loads the module that import refers to in the file at importer
+    pub fn load_import(
+        &mut self,
+        parser: &ProgramParser,
+        globals: &mut Globals,
+        importer: &str,
+        import: &ast::Import,
+        keep_going: bool,
+    ) -> Vec<LoferError> {
+        let loc = Location::at(importer, import.span);
+        match self.find(importer, &import.module) {
+            Ok(found) => {
+                self.load_from(parser, globals, &found, loc, keep_going)
+            },
+            Err(searched) => vec![LoferError::ModuleNotFound {
+                loc,
+                name: import.module.clone(),
+                searched,
+            }],
+        }
+    }
+
+    // checks contents, the file at path, after everything it imports
+    fn check_source(
+        &mut self,
+        parser: &ProgramParser,
+        globals: &mut Globals,
+        path: &str,
+        canonical: PathBuf,
+        contents: &str,
+        keep_going: bool,
+    ) -> Vec<LoferError> {
+        let (program, mut errors) = if keep_going {
+            parser.parse_recover(path, contents)
+        } else {
+            match parser.parse(path, contents) {
+                Ok(program) => (program, Vec::new()),
+                Err(e) => return vec![e],
+            }
+        };
+
+        self.loading.push((canonical.clone(), path.into()));
+        for item in &program {
+            let import = match &item.import {
+                Some(import) => import,
+                None => continue,
+            };
+            errors.extend(
+                self.load_import(parser, globals, path, import, keep_going));
+            if !errors.is_empty() && !keep_going {
+                self.loading.pop();
+                return errors;
+            }
+        }
+        self.loading.pop();
+
+        if !self.quiet {
+            println!("Type checking {}", path);
+        }
+        let module = Path::new(path).file_stem()
+            .map_or(String::new(), |stem| stem.to_string_lossy().into());
+        globals.enter_module(&module);
+        if !errors.is_empty() {
+            self.caching = false;
+        }
+        let options = globals.options;
+        let key = cache::key(self.state, options, &module, path, contents);
+        self.state = key;
+        self.loaded.push(canonical);
+        if let Some(printed) = self.restore(globals, key) {
+            self.print_checked(globals, &printed, true);
+            return errors;
+        }
+
+        let from = globals.names.len();
+        let data_from = globals.data_types.len();
+        let (checked, check_errors) =
+            check_items(globals, &program, keep_going);
+        self.print_checked(globals, &checked, check_errors.is_empty());
+        errors.extend(check_errors);
+        if !errors.is_empty() {
+            self.caching = false;
+        } else if let (Some(dir), true) = (&self.cache, self.caching) {
+            let entry = cache::save(globals, from, data_from);
+            if let Err(e) = save_entry(dir, key, &entry) {
+                eprintln!("warning: could not write to the cache in {}: {}",
+                    dir.display(), e);
+            }
+        }
+        errors
+    }
+
+    fn print_checked(&self, globals: &Globals, checked: &[usize],
+        success: bool)
+    {
+        if self.quiet {
+            return;
+        }
+        for &i in checked {
+            print_global(globals, i);
+        }
+        if success {
+            print!("Successfully type-checked all items!\n\n");
+        }
+    }
+
+    // adds the globals of the entry saved under key, if there is a valid one
+    fn restore(&self, globals: &mut Globals, key: u64)
+        -> Option<Vec<usize>>
+    {
+        let dir = self.cache.as_ref().filter(|_| self.caching)?;
+        let entry = fs::read_to_string(entry_path(dir, key)).ok()?;
+        cache::restore(globals, &entry)
+    }
+
+    // the file that module refers to when imported from importer, or else
+    // every path that was tried
+    fn find(&self, importer: &str, module: &str)
+        -> Result<String, Vec<String>>
+    {
+        let file = format!("{}.ls", module);
+        let own_dir = Path::new(importer).parent()
+            .map_or(PathBuf::new(), Path::to_path_buf);
+        let mut searched = Vec::new();
+        let dirs = Some(own_dir.as_path()).into_iter()
+            .chain(self.search_path.iter().map(PathBuf::as_path));
+        for dir in dirs {
+            let candidate = dir.join(&file);
+            if candidate.is_file() {
+                return Ok(candidate.to_string_lossy().into());
+            }
+            searched.push(candidate.to_string_lossy().into());
+        }
+        Err(searched)
+    }
+}
+
+fn entry_path(dir: &Path, key: u64) -> PathBuf {
+    dir.join(format!("{:016x}.lsc", key))
+}
+
+// writes to a temporary file first, so that another run never reads half of
+// an entry
+fn save_entry(dir: &Path, key: u64, entry: &str) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let path = entry_path(dir, key);
+    let temporary = path.with_extension("tmp");
+    fs::write(&temporary, entry)?;
+    fs::rename(&temporary, &path)
+}
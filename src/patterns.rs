@@ -0,0 +1,590 @@
+use std::rc::Rc;
+
+use ast;
+use error::{Goal, LoferError, Location};
+use pretty::{self, Pretty};
+
+use super::{Context, Def, Expr, Globals, Ident, Param, Scope};
+use super::{bind_params, binder_name, convert_expr, eval, subst};
+use super::type_check_expr;
+use unify::Metas;
+
+// a pattern whose names have been resolved to constructors or variables
+#[derive(Clone)]
+enum Pat {
+    Var(String),
+    Con { name: String, args: Vec<Pat>, span: ast::Span },
+}
+
+// what the values of a case we have split on look like, for describing the
+// cases that no clause covers
+#[derive(Clone)]
+enum Shape {
+    Hole,
+    Con(usize, Vec<Shape>),
+}
+
+// a clause that could still apply, with a pattern for each variable in the
+// context, and the variables it binds that have since been split on, along
+// with the number of data type parameters their constructor was given
+struct Row {
+    pats: Vec<Pat>,
+    bindings: Vec<(String, Expr, usize)>,
+    clause: usize,
+}
+
+struct Problem {
+    // valid in the context of the types before them
+    types: Vec<Expr>,
+    // valid in the context of all of the types
+    result: Expr,
+    rows: Vec<Row>,
+    shapes: Vec<Shape>,
+}
+
+pub fn is_constructor(globals: &Globals, name: &str) -> bool {
+    globals.data_types.iter()
+        .flat_map(|data| &data.constructors)
+        .any(|&c| globals.names[c] == name)
+}
+
+fn resolve(globals: &Globals, pattern: &ast::Pattern) -> Pat {
+    if pattern.args.is_empty() && !is_constructor(globals, &pattern.name) {
+        return Pat::Var(pattern.name.clone());
+    }
+    Pat::Con {
+        name: pattern.name.clone(),
+        args: pattern.args.iter().map(|p| resolve(globals, p)).collect(),
+        span: pattern.span,
+    }
+}
+
+// whether a definition has to be compiled into a case tree
+pub fn is_matching(globals: &Globals, fun: &ast::Item) -> bool {
+    let definition = fun.definition.as_ref().unwrap();
+    !fun.clauses.is_empty() || definition.patterns.iter().any(|p| {
+        !p.args.is_empty() || is_constructor(globals, &p.name)
+    })
+}
+
+// turns the clauses of a definition into a case tree over the constructors
+// of its parameters, made of applications of their data types' eliminators,
+// checking each clause in the case it applies to
+pub fn check_clauses(
+    globals: &Globals,
+    fun: &ast::Item,
+    annotation: &ast::Annotation,
+    ty: &Expr,
+    scope: &Scope,
+) -> Result<(usize, Def), LoferError> {
+    let definition = fun.definition.as_ref().unwrap();
+    let file = &fun.file;
+    if let Some(item) = fun.associated.first() {
+        let span = item.definition.as_ref()
+            .map(|d| d.span)
+            .or_else(|| item.annotation.as_ref().map(|a| a.span))
+            .unwrap_or(definition.span);
+        return Err(LoferError::Pattern {
+            loc: Location::at(file, span),
+            message: format!(
+                "{} matches on constructors, so it can't have associated items",
+                definition.fname),
+        });
+    }
+
+    let clauses: Vec<_> = Some(definition).into_iter()
+        .chain(&fun.clauses)
+        .collect();
+    let mut rows = Vec::with_capacity(clauses.len());
+    let mut param_num = None;
+    for (i, clause) in clauses.iter().enumerate() {
+        let patterns = bind_params(&annotation.typ, ty, &clause.patterns);
+        if param_num.is_some() && param_num != Some(patterns.len()) {
+            return Err(LoferError::Pattern {
+                loc: Location::at(file, clause.span),
+                message: format!(
+                    "every clause of {} must have the same number of patterns",
+                    definition.fname),
+            });
+        }
+        param_num = Some(patterns.len());
+        let mut pats: Vec<_> = scope.names.iter()
+            .map(|name| Pat::Var(name.clone()))
+            .collect();
+        pats.extend(patterns.iter().map(|p| resolve(globals, p)));
+        rows.push(Row { pats, bindings: Vec::new(), clause: i });
+    }
+    let param_num = param_num.unwrap();
+    if param_num > ty.arrow_params.len() {
+        return Err(LoferError::TooManyParameters {
+            loc: Location::at(file, definition.span),
+            name: definition.fname.clone(),
+            params: definition.patterns.len(),
+            arity: ty.arrow_params.iter().filter(|p| !p.implicit).count(),
+        });
+    }
+
+    let mut result = ty.clone();
+    let mut types = scope.types.clone();
+    types.extend(result.arrow_params.drain(0..param_num).map(|p| p.ty));
+    let shapes = vec![Shape::Hole; types.len()];
+    let problem = Problem { types, result, rows, shapes };
+
+    let mut compiler = Compiler {
+        globals,
+        file,
+        name: &definition.fname,
+        captured: scope.names.len(),
+        explicit: ty.arrow_params[..param_num].iter()
+            .map(|p| !p.implicit)
+            .collect(),
+        helpers: scope,
+        clauses: &clauses,
+        check: !annotation.is_post,
+        used: vec![false; clauses.len()],
+        goals: Vec::new(),
+    };
+    let tree = compiler.compile(problem)?;
+    if !compiler.goals.is_empty() {
+        let goals = compiler.goals;
+        return Err(LoferError::Holes { loc: goals[0].loc.clone(), goals });
+    }
+    if let Some(unused) = compiler.used.iter().position(|&used| !used) {
+        return Err(LoferError::UnreachableClause {
+            loc: Location::at(file, clauses[unused].span),
+            name: definition.fname.clone(),
+        });
+    }
+    Ok((param_num, Def::Body(tree)))
+}
+
+struct Compiler<'a> {
+    globals: &'a Globals,
+    file: &'a str,
+    name: &'a str,
+    // the number of variables that come from outside of the definition
+    captured: usize,
+    // which of the definition's own parameters were written by the user
+    explicit: Vec<bool>,
+    helpers: &'a Scope,
+    clauses: &'a [&'a ast::Function],
+    // postulates don't have their clauses checked
+    check: bool,
+    used: Vec<bool>,
+    // the holes in the bodies checked so far, which are reported together
+    // once every clause has been
+    goals: Vec<Goal>,
+}
+
+impl<'a> Compiler<'a> {
+    fn compile(&mut self, problem: Problem) -> Result<Expr, LoferError> {
+        if problem.rows.is_empty() {
+            let mut case = self.name.to_string();
+            let params = problem.shapes[self.captured..].iter()
+                .zip(&self.explicit)
+                .filter(|&(_, &explicit)| explicit);
+            for (shape, _) in params {
+                case.push(' ');
+                self.show(shape, true, &mut case);
+            }
+            return Err(LoferError::MissingCase {
+                loc: Location::at(self.file, self.clauses[0].span),
+                name: self.name.into(),
+                case,
+            });
+        }
+        let split = problem.rows[0].pats.iter().position(|pat| match pat {
+            Pat::Con { .. } => true,
+            Pat::Var(_) => false,
+        });
+        if let Some(i) = split {
+            self.split(problem, i)
+        } else {
+            self.leaf(problem)
+        }
+    }
+
+    fn show(&self, shape: &Shape, grouped: bool, out: &mut String) {
+        match shape {
+            Shape::Hole => out.push('_'),
+            Shape::Con(c, args) if args.is_empty() => {
+                out.push_str(&self.globals.names[*c]);
+            },
+            Shape::Con(c, args) => {
+                if grouped {
+                    out.push('(');
+                }
+                out.push_str(&self.globals.names[*c]);
+                for arg in args {
+                    out.push(' ');
+                    self.show(arg, true, out);
+                }
+                if grouped {
+                    out.push(')');
+                }
+            },
+        }
+    }
+
+    // the first remaining clause matches everything left, so check its body
+    fn leaf(&mut self, problem: Problem) -> Result<Expr, LoferError> {
+        let Problem { types, result, mut rows, .. } = problem;
+        let row = rows.swap_remove(0);
+        self.used[row.clause] = true;
+        let clause = self.clauses[row.clause];
+
+        let ctx_size = types.len();
+        let mut names: Vec<String> = row.pats.into_iter()
+            .map(|pat| match pat {
+                Pat::Var(name) => name,
+                Pat::Con { .. } => unreachable!(),
+            })
+            .collect();
+        let mut values = Vec::with_capacity(row.bindings.len());
+        for (name, mut value, np) in row.bindings {
+            // the body hasn't been checked yet, so leave the parameters for
+            // it to infer like any other implicit arguments
+            value.tail.drain(0..np);
+            names.push(name);
+            values.push(value);
+        }
+        let (body, mut spans) = convert_expr(
+            self.globals,
+            &self.helpers.helpers,
+            &Context::new(&names),
+            clause.body.clone(),
+        ).map_err(|e| e.report(self.globals, self.file, &names))?;
+        // variables that were split on by other clauses stand for the
+        // constructors they were split into
+        let mut body = subst(&body, ctx_size, 0, &values, ctx_size);
+        spans.fit(&body);
+        names.truncate(ctx_size);
+
+        if self.check {
+            let mut metas = Metas::default();
+            let checked = type_check_expr(
+                self.globals,
+                &mut metas,
+                &Context::new(&types),
+                &mut body,
+                &mut spans,
+                Some(&result),
+            ).and_then(|_| {
+                metas.finish(&mut body, ctx_size)
+            }).map_err(|e| e.report(self.globals, self.file, &names));
+            match checked {
+                Err(LoferError::Holes { goals, .. }) => {
+                    self.goals.extend(goals);
+                },
+                checked => checked?,
+            }
+        }
+        Ok(body)
+    }
+
+    // eliminates variable i, with a method for each constructor of its type
+    // that replaces it with the constructor's fields, and takes back the
+    // variables after it, since their types can depend on it
+    fn split(&mut self, problem: Problem, i: usize)
+        -> Result<Expr, LoferError>
+    {
+        let globals = self.globals;
+        let Problem { types, result, rows, shapes } = problem;
+        let (con_name, con_span) = match &rows[0].pats[i] {
+            Pat::Con { name, span, .. } => (name.clone(), *span),
+            Pat::Var(_) => unreachable!(),
+        };
+        let ty = &types[i];
+        let data = match ty.head {
+            Ident::Global(d) if ty.lambda_params == 0
+                && ty.arrow_params.is_empty() =>
+            {
+                globals.data_types.iter().find(|data| data.index == d)
+            },
+            _ => None,
+        };
+        let data = match data {
+            Some(data) => data,
+            None => {
+                let names = pretty::local_names(globals, &[], i);
+                return Err(LoferError::Pattern {
+                    loc: Location::at(self.file, con_span),
+                    message: format!(
+                        "cannot match {} against a constructor, since it has \
+                        type {}, which isn't a data type",
+                        con_name, Pretty::new(globals, &names, ty)),
+                });
+            },
+        };
+        if data.indices > 0 {
+            return Err(LoferError::Pattern {
+                loc: Location::at(self.file, con_span),
+                message: format!(
+                    "pattern matching on indexed data types like {} isn't \
+                    supported yet, use {}_elim instead",
+                    globals.names[data.index], globals.names[data.index]),
+            });
+        }
+        for row in &rows {
+            if let Pat::Con { name, span, .. } = &row.pats[i] {
+                let known = data.constructors.iter()
+                    .any(|&c| globals.names[c] == *name);
+                if !known {
+                    return Err(LoferError::Pattern {
+                        loc: Location::at(self.file, *span),
+                        message: format!("{} is not a constructor of {}",
+                            name, globals.names[data.index]),
+                    });
+                }
+            }
+        }
+
+        let np = data.params;
+        let params = &ty.tail[..np];
+        let n = types.len();
+        let later = n - i - 1;
+        let elim = data.eliminator();
+        let elim_ty = &globals.defs[elim].ty;
+        let mut methods = Vec::with_capacity(data.constructors.len());
+        for (m, &c) in data.constructors.iter().enumerate() {
+            let con_ty = &globals.defs[c].ty;
+            let nf = con_ty.arrow_params.len() - np;
+            let ctx_size = n + nf - 1;
+
+            // the value of variable i in this case
+            let mut value = Expr::from_head(Ident::Global(c));
+            value.tail = params.to_vec();
+            value.tail.extend((i..i + nf).map(|l| {
+                Expr::from_head(Ident::Local(l))
+            }));
+            let replace = |ex: &Expr, level: usize| {
+                let mut ex = subst(ex, i, 0, &[value.clone()], i + nf);
+                eval(&globals.defs, &mut ex, level);
+                ex
+            };
+
+            let mut new_types = types[..i].to_vec();
+            for (j, field) in con_ty.arrow_params[np..].iter().enumerate() {
+                let mut field = subst(&field.ty, 0, 0, params, i);
+                eval(&globals.defs, &mut field, i + j);
+                new_types.push(field);
+            }
+            for (k, ty) in types.iter().enumerate().skip(i + 1) {
+                new_types.push(replace(ty, k + nf - 1));
+            }
+            let new_result = replace(&result, ctx_size);
+
+            let mut new_rows = Vec::new();
+            for row in &rows {
+                let mut bindings: Vec<_> = row.bindings.iter()
+                    .map(|(name, ex, np)| {
+                        (name.clone(), replace(ex, ctx_size), *np)
+                    })
+                    .collect();
+                // what the pattern at i becomes in this case, if it applies
+                let expanded: Vec<Pat> = match &row.pats[i] {
+                    Pat::Con { name, .. } if globals.names[c] != *name => {
+                        continue;
+                    },
+                    Pat::Con { name, args, span } => {
+                        if args.len() != nf {
+                            return Err(LoferError::Pattern {
+                                loc: Location::at(self.file, *span),
+                                message: format!(
+                                    "constructor {} takes {} arguments, but \
+                                    was given {}",
+                                    name, nf, args.len()),
+                            });
+                        }
+                        args.clone()
+                    },
+                    Pat::Var(name) => {
+                        bindings.push((name.clone(), value.clone(), np));
+                        (0..nf).map(|_| Pat::Var("_".into())).collect()
+                    },
+                };
+                let mut pats = row.pats[..i].to_vec();
+                pats.extend(expanded);
+                pats.extend_from_slice(&row.pats[i + 1..]);
+                new_rows.push(Row { pats, bindings, clause: row.clause });
+            }
+
+            // the method's binders are named after the variables the clauses
+            // bind there, which are the fields and then the later variables
+            let mut names: Vec<_> = (i..i + nf)
+                .map(|k| var_name(&new_rows, k))
+                .collect();
+            let hypotheses =
+                elim_ty.arrow_params[np + 1 + m].ty.arrow_params.len() - nf;
+            names.extend((0..hypotheses).map(|_| None));
+            names.extend((i + nf..n + nf - 1).map(|k| var_name(&new_rows, k)));
+
+            let new_shapes = refine(&shapes, i, c, nf);
+            let sub = self.compile(Problem {
+                types: new_types,
+                result: new_result,
+                rows: new_rows,
+                shapes: new_shapes,
+            })?;
+
+            // the method takes the fields, the eliminator's inductive
+            // hypotheses, which recursive calls don't need, and then the
+            // variables after i again
+            let inner = n + nf + hypotheses;
+            let mut vars = locals(n..n + nf);
+            vars.extend(locals(inner..inner + later));
+            let mut method = subst(&sub, i, 0, &vars, inner + later);
+            method.lambda_params += nf + hypotheses + later;
+            method.lambda_names.splice(0..0, names);
+            methods.push(method);
+        }
+
+        // what the rest of the definition gives for each value of i, and of
+        // the variables after it
+        let mut motive = subst(&result, i, 0, &locals(n..n + 1 + later),
+            n + 1 + later);
+        let reverted = types[i + 1..].iter().enumerate().map(|(k, ty)| {
+            Param {
+                implicit: false,
+                ty: subst(ty, i, 0, &locals(n..n + 1 + k), n + 1 + k),
+                name: var_name(&rows, i + 1 + k),
+            }
+        });
+        motive.arrow_params.splice(0..0, reverted);
+        motive.lambda_params = 1;
+        motive.lambda_names = vec![var_name(&rows, i)];
+
+        let mut tree = Expr::from_head(Ident::Global(elim));
+        tree.tail = params.to_vec();
+        tree.tail.push(motive);
+        tree.tail.extend(methods);
+        tree.tail.extend(locals(i..n));
+        Ok(tree)
+    }
+}
+
+// one case of a split, for a constructor of the data type split on
+pub struct Case {
+    pub constructor: usize,
+    // the constructor's own fields, after the data type's parameters, named
+    // after the variables that the clauses bound them to where they did
+    pub fields: Vec<Param>,
+    // how many inductive hypotheses the eliminator gives after the fields
+    pub hypotheses: usize,
+    // in a context of ctx_size, which is the split's followed by the fields,
+    // the hypotheses and the variables after the one split on again
+    pub body: Expr,
+    pub ctx_size: usize,
+    // the levels of the variables that the case tree can still match on,
+    // with the fields in place of the variable that was split on
+    pub vars: Vec<usize>,
+}
+
+// the split on a variable that a case tree starts with, given by its
+// position in the variables that the tree can match on
+pub struct Split {
+    pub major: usize,
+    pub params: usize,
+    pub cases: Vec<Case>,
+}
+
+// the split that tree makes, if it is an eliminator applied to one of vars and
+// to every one after it, which is how split builds them, where vars are the
+// levels of the variables it can match on in a context of ctx_size
+pub fn as_split(
+    globals: &Globals,
+    tree: &Expr,
+    vars: &[usize],
+    ctx_size: usize,
+) -> Option<Split> {
+    if tree.lambda_params > 0 || !tree.arrow_params.is_empty() {
+        return None;
+    }
+    let elim = match tree.head {
+        Ident::Global(elim) => elim,
+        _ => return None,
+    };
+    let data = globals.data_types.iter()
+        .find(|data| data.indices == 0 && data.eliminator() == elim)?;
+    let np = data.params;
+    let first = np + 1 + data.constructors.len();
+    let major = (0..vars.len()).find(|&j| {
+        tree.tail.len() == first + vars.len() - j
+            && tree.tail[first..].iter()
+                .zip(&vars[j..])
+                .all(|(ex, &l)| *ex == Expr::from_head(Ident::Local(l)))
+    })?;
+    let later = vars.len() - major - 1;
+
+    let elim_ty = &globals.defs[elim].ty;
+    let mut cases = Vec::with_capacity(data.constructors.len());
+    for (m, &c) in data.constructors.iter().enumerate() {
+        let fields = &globals.defs[c].ty.arrow_params[np..];
+        let nf = fields.len();
+        let method_ty = &elim_ty.arrow_params[np + 1 + m].ty;
+        let hypotheses = method_ty.arrow_params.len() - nf;
+        let bound = nf + hypotheses + later;
+        let mut body = tree.tail[np + 1 + m].clone();
+        if body.lambda_params < bound {
+            return None;
+        }
+        body.lambda_params -= bound;
+        let names: Vec<_> = body.lambda_names.drain(0..bound).collect();
+        let fields = fields.iter().zip(names)
+            .map(|(field, name)| Param {
+                name: name.or_else(|| field.name.clone()),
+                ..field.clone()
+            })
+            .collect();
+        let reverted = ctx_size + nf + hypotheses;
+        let mut case_vars = vars[..major].to_vec();
+        case_vars.extend(ctx_size..ctx_size + nf);
+        case_vars.extend(reverted..reverted + later);
+        cases.push(Case {
+            constructor: c,
+            fields,
+            hypotheses,
+            body,
+            ctx_size: reverted + later,
+            vars: case_vars,
+        });
+    }
+    Some(Split { major, params: np, cases })
+}
+
+// what the first clause that names the variable at k calls it
+fn var_name(rows: &[Row], k: usize) -> Option<Rc<str>> {
+    rows.iter().find_map(|row| match &row.pats[k] {
+        Pat::Var(name) => binder_name(name),
+        Pat::Con { .. } => None,
+    })
+}
+
+fn locals(range: ::std::ops::Range<usize>) -> Vec<Expr> {
+    range.map(|l| Expr::from_head(Ident::Local(l))).collect()
+}
+
+// replaces the hole for variable i with an application of constructor c,
+// since the variables in the context are the holes in order
+fn refine(shapes: &[Shape], i: usize, c: usize, nf: usize) -> Vec<Shape> {
+    fn go(shape: &Shape, i: usize, c: usize, nf: usize, seen: &mut usize)
+        -> Shape
+    {
+        match shape {
+            Shape::Hole => {
+                *seen += 1;
+                if *seen == i + 1 {
+                    Shape::Con(c, vec![Shape::Hole; nf])
+                } else {
+                    Shape::Hole
+                }
+            },
+            Shape::Con(d, args) => Shape::Con(
+                *d,
+                args.iter().map(|arg| go(arg, i, c, nf, seen)).collect(),
+            ),
+        }
+    }
+    let mut seen = 0;
+    shapes.iter().map(|shape| go(shape, i, c, nf, &mut seen)).collect()
+}
@@ -0,0 +1,233 @@
+use std::fmt;
+
+use prims;
+
+use super::{Expr, Globals, Ident, Param};
+
+// displays an expression using the names that the user wrote, given the
+// names of the locals it can refer to (indexed by de Bruijn level)
+// binders inside the expression are invented as needed, and only written
+// out when something actually depends on them
+pub struct Pretty<'a> {
+    globals: &'a Globals,
+    names: &'a [String],
+    expr: &'a Expr,
+}
+
+impl<'a> Pretty<'a> {
+    pub fn new(globals: &'a Globals, names: &'a [String], expr: &'a Expr)
+        -> Self
+    {
+        Pretty { globals, names, expr }
+    }
+}
+
+impl<'a> fmt::Display for Pretty<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut names = self.names.to_vec();
+        write_expr(self.globals, &mut names, self.expr, f)
+    }
+}
+
+// extends some known local names to cover a context of the given size, for
+// when an expression lives under binders that were never named
+pub fn local_names(globals: &Globals, known: &[String], ctx_size: usize)
+    -> Vec<String>
+{
+    let mut names = known.to_vec();
+    while names.len() < ctx_size {
+        let name = fresh_name(globals, &names, TERM_NAMES);
+        names.push(name);
+    }
+    names
+}
+
+// names for some parameters, which are the ones the user gave them where
+// possible, that don't clash with the names that are already taken
+pub fn param_names(globals: &Globals, taken: &[String], params: &[Param])
+    -> Vec<String>
+{
+    let mut names = taken.to_vec();
+    for param in params {
+        let name = binder_name(globals, &names, param);
+        names.push(name);
+    }
+    names.split_off(taken.len())
+}
+
+const TYPE_NAMES: &[&str] = &["A", "B", "C", "D", "E"];
+const FAMILY_NAMES: &[&str] = &["F", "G", "H"];
+const TERM_NAMES: &[&str] = &["x", "y", "z", "w"];
+const LEVEL_NAMES: &[&str] = &["l", "m", "n"];
+
+fn is_taken(globals: &Globals, names: &[String], name: &str) -> bool {
+    names.iter().any(|n| n == name)
+        || globals.names.iter().any(|n| n == name)
+        || globals.short_names.iter().any(|n| n == name)
+}
+
+fn fresh_name(globals: &Globals, names: &[String], pool: &[&str]) -> String {
+    for &name in pool {
+        if !is_taken(globals, names, name) {
+            return name.into();
+        }
+    }
+    let mut i = 1;
+    loop {
+        let name = format!("{}{}", pool[0], i);
+        if !is_taken(globals, names, &name) {
+            return name;
+        }
+        i += 1;
+    }
+}
+
+fn binder_pool(ty: &Expr) -> &'static [&'static str] {
+    if ty.is_universe() {
+        TYPE_NAMES
+    } else if *ty == Expr::from_head(Ident::Global(prims::LEVEL)) {
+        LEVEL_NAMES
+    } else if ty.tail.is_empty() && !ty.arrow_params.is_empty() {
+        match ty.head {
+            Ident::Universe(_) => FAMILY_NAMES,
+            _ => TERM_NAMES,
+        }
+    } else {
+        TERM_NAMES
+    }
+}
+
+fn binder_name(globals: &Globals, names: &[String], param: &Param) -> String {
+    match param.name {
+        Some(ref name) => fresh_name(globals, names, &[name]),
+        None => fresh_name(globals, names, binder_pool(&param.ty)),
+    }
+}
+
+// which arguments of an application are implicit, which are hidden since
+// the user wouldn't have written them
+fn implicit_args(globals: &Globals, head: &Ident) -> Vec<bool> {
+    match *head {
+        Ident::Global(i) => globals.defs[i].ty.arrow_params.iter()
+            .map(|p| p.implicit)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn has_visible_args(globals: &Globals, expr: &Expr) -> bool {
+    let implicit = implicit_args(globals, &expr.head);
+    (0..expr.tail.len()).any(|k| implicit.get(k) != Some(&true))
+}
+
+fn mentions(expr: &Expr, level: usize) -> bool {
+    expr.head == Ident::Local(level)
+        || expr.arrow_params.iter().any(|p| mentions(&p.ty, level))
+        || expr.tail.iter().any(|ex| mentions(ex, level))
+}
+
+fn write_expr(
+    globals: &Globals,
+    names: &mut Vec<String>,
+    expr: &Expr,
+    f: &mut fmt::Formatter,
+) -> fmt::Result {
+    let outer = names.len();
+    if expr.lambda_params > 0 {
+        write!(f, "\\")?;
+        for k in 0..expr.lambda_params {
+            let name = match expr.lambda_names.get(k) {
+                Some(Some(name)) => fresh_name(globals, names, &[name]),
+                _ => fresh_name(globals, names, TERM_NAMES),
+            };
+            write!(f, "{} ", name)?;
+            names.push(name);
+        }
+        write!(f, "-> ")?;
+    }
+    let arrows_start = names.len();
+    for (k, param) in expr.arrow_params.iter().enumerate() {
+        let level = arrows_start + k;
+        let used = expr.arrow_params[k + 1..].iter()
+                .any(|p| mentions(&p.ty, level))
+            || expr.head == Ident::Local(level)
+            || expr.tail.iter().any(|ex| mentions(ex, level));
+        // implicit binders are always named, so that they look implicit
+        if used || param.implicit {
+            let name = binder_name(globals, names, param);
+            let (open, close) = if param.implicit { ("{", "}") } else { ("(", ")") };
+            write!(f, "{}{}: ", open, name)?;
+            write_expr(globals, names, &param.ty, f)?;
+            write!(f, "{} -> ", close)?;
+            names.push(name);
+        } else {
+            write_grouped(globals, names, &param.ty, f, false)?;
+            write!(f, " -> ")?;
+            // never referred to, so it doesn't matter what we call it
+            names.push("_".into());
+        }
+    }
+    match expr.head {
+        Ident::Universe(prims::OMEGA) => write!(f, "Uω")?,
+        Ident::Universe(l) => write!(f, "U{}", l)?,
+        Ident::Local(i) => {
+            if i < names.len() {
+                write!(f, "{}", names[i])?;
+            } else {
+                write!(f, "_x{}", i)?;
+            }
+        },
+        Ident::Global(i) => write!(f, "{}", globals.names[i])?,
+        Ident::Overload(i) => write!(f, "{}", globals.short_names[i])?,
+        Ident::Meta(m) => write!(f, "?{}", m)?,
+        Ident::Hole(Some(ref name)) => write!(f, "?{}", name)?,
+        Ident::Hole(None) => write!(f, "?")?,
+        Ident::Nat(n) => write!(f, "{}", n)?,
+        Ident::Int(n) => write!(f, "{:+}", n)?,
+        Ident::Str(ref s) => write_string(s, f)?,
+    }
+    let implicit = implicit_args(globals, &expr.head);
+    for (k, ex) in expr.tail.iter().enumerate() {
+        if implicit.get(k) == Some(&true) {
+            continue;
+        }
+        write!(f, " ")?;
+        write_grouped(globals, names, ex, f, true)?;
+    }
+    names.truncate(outer);
+    Ok(())
+}
+
+// the inverse of the escapes that string literals allow
+fn write_string(s: &str, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '\n' => write!(f, "\\n")?,
+            '\t' => write!(f, "\\t")?,
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            _ => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+fn write_grouped(
+    globals: &Globals,
+    names: &mut Vec<String>,
+    expr: &Expr,
+    f: &mut fmt::Formatter,
+    group_algs: bool,
+) -> fmt::Result {
+    if expr.lambda_params > 0
+        || !expr.arrow_params.is_empty()
+        || (group_algs && has_visible_args(globals, expr))
+    {
+        write!(f, "(")?;
+        write_expr(globals, names, expr, f)?;
+        write!(f, ")")
+    } else {
+        write_expr(globals, names, expr, f)
+    }
+}
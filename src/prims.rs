@@ -0,0 +1,355 @@
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+use super::{Def, Expr, Globals, Ident, Item, Param};
+
+// the builtins are always the first globals, in this order
+pub const NAT: usize = 0;
+pub const INT: usize = 1;
+pub const STRING: usize = 2;
+pub const LEVEL: usize = 3;
+pub const UNIVERSE: usize = 4;
+pub const LEVEL_SUC: usize = 5;
+pub const LEVEL_MAX: usize = 6;
+
+// the universe that arrows whose level depends on their own parameters live
+// in, like `(l: Level) -> U l`, which is above every other
+pub const OMEGA: usize = usize::MAX;
+
+// the types that primitive operations take and return, where Bool is
+// `(A: U0) -> A -> A -> A`
+#[derive(Clone, Copy)]
+enum Ty {
+    Nat,
+    Int,
+    Str,
+    Level,
+    Bool,
+}
+
+// operations on literals that eval computes natively, once all of their
+// arguments are literals
+// Nat subtraction stops at zero, Int arithmetic wraps around, and dividing by
+// zero never computes
+#[derive(Clone, Copy, PartialEq)]
+pub enum Prim {
+    Universe,
+    LevelSuc,
+    LevelMax,
+    NatAdd,
+    NatSub,
+    NatMul,
+    NatDiv,
+    NatMod,
+    NatEq,
+    NatLt,
+    NatShow,
+    IntAdd,
+    IntSub,
+    IntMul,
+    IntDiv,
+    IntMod,
+    IntEq,
+    IntLt,
+    IntNegate,
+    IntFromNat,
+    IntAbs,
+    IntShow,
+    StringAppend,
+    StringLength,
+    StringEq,
+}
+
+const PRIMS: &[(&str, Prim, &[Ty], Ty)] = &[
+    ("Level_suc", Prim::LevelSuc, &[Ty::Level], Ty::Level),
+    ("Level_max", Prim::LevelMax, &[Ty::Level, Ty::Level], Ty::Level),
+    ("Nat_add", Prim::NatAdd, &[Ty::Nat, Ty::Nat], Ty::Nat),
+    ("Nat_sub", Prim::NatSub, &[Ty::Nat, Ty::Nat], Ty::Nat),
+    ("Nat_mul", Prim::NatMul, &[Ty::Nat, Ty::Nat], Ty::Nat),
+    ("Nat_div", Prim::NatDiv, &[Ty::Nat, Ty::Nat], Ty::Nat),
+    ("Nat_mod", Prim::NatMod, &[Ty::Nat, Ty::Nat], Ty::Nat),
+    ("Nat_eq", Prim::NatEq, &[Ty::Nat, Ty::Nat], Ty::Bool),
+    ("Nat_lt", Prim::NatLt, &[Ty::Nat, Ty::Nat], Ty::Bool),
+    ("Nat_show", Prim::NatShow, &[Ty::Nat], Ty::Str),
+    ("Int_add", Prim::IntAdd, &[Ty::Int, Ty::Int], Ty::Int),
+    ("Int_sub", Prim::IntSub, &[Ty::Int, Ty::Int], Ty::Int),
+    ("Int_mul", Prim::IntMul, &[Ty::Int, Ty::Int], Ty::Int),
+    ("Int_div", Prim::IntDiv, &[Ty::Int, Ty::Int], Ty::Int),
+    ("Int_mod", Prim::IntMod, &[Ty::Int, Ty::Int], Ty::Int),
+    ("Int_eq", Prim::IntEq, &[Ty::Int, Ty::Int], Ty::Bool),
+    ("Int_lt", Prim::IntLt, &[Ty::Int, Ty::Int], Ty::Bool),
+    ("Int_negate", Prim::IntNegate, &[Ty::Int], Ty::Int),
+    ("Int_from_nat", Prim::IntFromNat, &[Ty::Nat], Ty::Int),
+    ("Int_abs", Prim::IntAbs, &[Ty::Int], Ty::Nat),
+    ("Int_show", Prim::IntShow, &[Ty::Int], Ty::Str),
+    ("String_append", Prim::StringAppend, &[Ty::Str, Ty::Str], Ty::Str),
+    ("String_length", Prim::StringLength, &[Ty::Str], Ty::Nat),
+    ("String_eq", Prim::StringEq, &[Ty::Str, Ty::Str], Ty::Bool),
+];
+
+// the builtins that overload a shorter name, the rest only have their own
+const SHORT_NAMES: &[(&str, &str)] = &[
+    ("Level_suc", "suc"),
+    ("Level_max", "max"),
+];
+
+// the type of a primitive parameter or result, in a context of ctx_size
+fn ty_expr(ty: Ty, ctx_size: usize) -> Expr {
+    match ty {
+        Ty::Nat => Expr::from_head(Ident::Global(NAT)),
+        Ty::Int => Expr::from_head(Ident::Global(INT)),
+        Ty::Str => Expr::from_head(Ident::Global(STRING)),
+        Ty::Level => Expr::from_head(Ident::Global(LEVEL)),
+        Ty::Bool => {
+            let a = || Expr::from_head(Ident::Local(ctx_size));
+            let mut result = a();
+            result.arrow_params = vec![
+                Param { implicit: false, ty: Expr::universe(0), name: None },
+                Param { implicit: false, ty: a(), name: None },
+                Param { implicit: false, ty: a(), name: None },
+            ];
+            result
+        },
+    }
+}
+
+// adds the primitive types and operations, which user definitions with the
+// same names shadow
+pub fn add_builtins(globals: &mut Globals) {
+    for name in &["Nat", "Int", "String", "Level"] {
+        let item = Item { ty: Expr::universe(0), def: None };
+        globals.push(name.to_string(), name.to_string(), None, item);
+    }
+    // U: (l: Level) -> U (Level_suc l)
+    let mut ty = universe(level_suc(Expr::from_head(Ident::Local(0))));
+    ty.arrow_params.push(Param {
+        implicit: false,
+        ty: ty_expr(Ty::Level, 0),
+        name: None,
+    });
+    let item = Item { ty, def: Some((1, Def::Prim(Prim::Universe))) };
+    globals.push("U".into(), "U".into(), None, item);
+    for &(name, prim, params, result) in PRIMS {
+        let mut ty = ty_expr(result, params.len());
+        ty.arrow_params.splice(0..0, params.iter().map(|&param| {
+            Param { implicit: false, ty: ty_expr(param, 0), name: None }
+        }));
+        let item = Item { ty, def: Some((params.len(), Def::Prim(prim))) };
+        let short_name = SHORT_NAMES.iter()
+            .find(|&&(full, _)| full == name)
+            .map_or(name, |&(_, short)| short);
+        globals.push(name.to_string(), short_name.to_string(), None, item);
+    }
+}
+
+// the name of the builtin that computes with prim
+pub fn name(prim: Prim) -> &'static str {
+    if prim == Prim::Universe {
+        return "U";
+    }
+    PRIMS.iter().find(|each| each.1 == prim).unwrap().0
+}
+
+pub fn by_name(name: &str) -> Option<Prim> {
+    if name == "U" {
+        return Some(Prim::Universe);
+    }
+    PRIMS.iter().find(|each| each.0 == name).map(|each| each.1)
+}
+
+// U l, for a level that might not be a literal
+pub fn universe(level: Expr) -> Expr {
+    let mut result = Expr::from_head(Ident::Global(UNIVERSE));
+    result.tail.push(level);
+    result
+}
+
+fn level_suc(level: Expr) -> Expr {
+    let mut result = Expr::from_head(Ident::Global(LEVEL_SUC));
+    result.tail.push(level);
+    result
+}
+
+// the level of a universe, as a level expression
+pub fn universe_level(expr: &Expr) -> Option<Expr> {
+    if let Some(l) = expr.universe_level() {
+        if l == OMEGA {
+            return None;
+        }
+        return Some(Expr::from_head(Ident::Nat(l as u64)));
+    }
+    let is_universe = expr.lambda_params == 0
+        && expr.arrow_params.is_empty()
+        && expr.head == Ident::Global(UNIVERSE)
+        && expr.tail.len() == 1;
+    if is_universe {
+        Some(expr.tail[0].clone())
+    } else {
+        None
+    }
+}
+
+// the level one below a level expression, if it has one
+pub fn level_pred(level: &Expr) -> Option<Expr> {
+    if let Some(n) = nat(level) {
+        return n.checked_sub(1).map(|n| Expr::from_head(Ident::Nat(n)));
+    }
+    let is_suc = level.lambda_params == 0
+        && level.arrow_params.is_empty()
+        && level.head == Ident::Global(LEVEL_SUC)
+        && level.tail.len() == 1;
+    if is_suc {
+        Some(level.tail[0].clone())
+    } else {
+        None
+    }
+}
+
+fn nat(expr: &Expr) -> Option<u64> {
+    match literal(expr)? {
+        Ident::Nat(n) => Some(*n),
+        _ => None,
+    }
+}
+
+fn int(expr: &Expr) -> Option<i64> {
+    match literal(expr)? {
+        Ident::Int(n) => Some(*n),
+        _ => None,
+    }
+}
+
+fn string(expr: &Expr) -> Option<&str> {
+    match literal(expr)? {
+        Ident::Str(s) => Some(s),
+        _ => None,
+    }
+}
+
+fn literal(expr: &Expr) -> Option<&Ident> {
+    if expr.lambda_params > 0
+        || !expr.arrow_params.is_empty()
+        || !expr.tail.is_empty()
+    {
+        return None;
+    }
+    Some(&expr.head)
+}
+
+// \A x y -> x or \A x y -> y, in a context of ctx_size variables
+fn boolean(b: bool, ctx_size: usize) -> Expr {
+    let chosen = if b { ctx_size + 1 } else { ctx_size + 2 };
+    let mut result = Expr::from_head(Ident::Local(chosen));
+    result.lambda_params = 3;
+    result.lambda_names = vec![None; 3];
+    result
+}
+
+// the two sides of a max of levels
+fn level_max_args(level: &Expr) -> Option<(&Expr, &Expr)> {
+    let is_max = level.lambda_params == 0
+        && level.arrow_params.is_empty()
+        && level.head == Ident::Global(LEVEL_MAX)
+        && level.tail.len() == 2;
+    if is_max {
+        Some((&level.tail[0], &level.tail[1]))
+    } else {
+        None
+    }
+}
+
+// whether level a is at most level b, as far as can be told without knowing
+// what the variables in them are
+pub fn level_leq(a: &Expr, b: &Expr) -> bool {
+    if a == b || nat(a) == Some(0) {
+        return true;
+    }
+    if let (Some(x), Some(y)) = (nat(a), nat(b)) {
+        return x <= y;
+    }
+    if let Some((x, y)) = level_max_args(a) {
+        return level_leq(x, b) && level_leq(y, b);
+    }
+    if let Some((x, y)) = level_max_args(b) {
+        return level_leq(a, x) || level_leq(a, y);
+    }
+    match (level_pred(a), level_pred(b)) {
+        (Some(pa), Some(pb)) => level_leq(&pa, &pb),
+        (None, Some(pb)) => level_leq(a, &pb),
+        _ => false,
+    }
+}
+
+// the result of a primitive applied to exactly its arguments, if they are
+// all literals
+pub fn apply(prim: Prim, args: &[Expr], ctx_size: usize) -> Option<Expr> {
+    let lit = |ident| Some(Expr::from_head(ident));
+    let nats = || Some((nat(&args[0])?, nat(&args[1])?));
+    let ints = || Some((int(&args[0])?, int(&args[1])?));
+    match prim {
+        Prim::Universe => {
+            let l = nat(&args[0])? as usize;
+            if l < OMEGA { Some(Expr::universe(l)) } else { None }
+        },
+        Prim::LevelSuc => nat(&args[0])?
+            .checked_add(1)
+            .and_then(|n| lit(Ident::Nat(n))),
+        // max is only taken apart far enough to see that both sides are the
+        // same, or that one of them is zero
+        Prim::LevelMax => match (nat(&args[0]), nat(&args[1])) {
+            (Some(a), Some(b)) => lit(Ident::Nat(a.max(b))),
+            (Some(0), _) => Some(args[1].clone()),
+            (_, Some(0)) => Some(args[0].clone()),
+            _ if args[0] == args[1] => Some(args[0].clone()),
+            _ => None,
+        },
+        Prim::NatAdd => nats()
+            .and_then(|(a, b)| a.checked_add(b))
+            .and_then(|n| lit(Ident::Nat(n))),
+        Prim::NatSub => nats()
+            .and_then(|(a, b)| lit(Ident::Nat(a.saturating_sub(b)))),
+        Prim::NatMul => nats()
+            .and_then(|(a, b)| a.checked_mul(b))
+            .and_then(|n| lit(Ident::Nat(n))),
+        Prim::NatDiv => nats()
+            .and_then(|(a, b)| a.checked_div(b))
+            .and_then(|n| lit(Ident::Nat(n))),
+        Prim::NatMod => nats()
+            .and_then(|(a, b)| a.checked_rem(b))
+            .and_then(|n| lit(Ident::Nat(n))),
+        Prim::NatEq => nats().map(|(a, b)| boolean(a == b, ctx_size)),
+        Prim::NatLt => nats().map(|(a, b)| boolean(a < b, ctx_size)),
+        Prim::NatShow => lit(Ident::Str(nat(&args[0])?.to_string().into())),
+        Prim::IntAdd => ints()
+            .and_then(|(a, b)| lit(Ident::Int(a.wrapping_add(b)))),
+        Prim::IntSub => ints()
+            .and_then(|(a, b)| lit(Ident::Int(a.wrapping_sub(b)))),
+        Prim::IntMul => ints()
+            .and_then(|(a, b)| lit(Ident::Int(a.wrapping_mul(b)))),
+        Prim::IntDiv => ints()
+            .and_then(|(a, b)| a.checked_div(b))
+            .and_then(|n| lit(Ident::Int(n))),
+        Prim::IntMod => ints()
+            .and_then(|(a, b)| a.checked_rem(b))
+            .and_then(|n| lit(Ident::Int(n))),
+        Prim::IntEq => ints().map(|(a, b)| boolean(a == b, ctx_size)),
+        Prim::IntLt => ints().map(|(a, b)| boolean(a < b, ctx_size)),
+        Prim::IntNegate => lit(Ident::Int(int(&args[0])?.wrapping_neg())),
+        // naturals too big to be an Int stay as they are
+        Prim::IntFromNat => {
+            lit(Ident::Int(i64::try_from(nat(&args[0])?).ok()?))
+        },
+        Prim::IntAbs => lit(Ident::Nat(int(&args[0])?.unsigned_abs())),
+        Prim::IntShow => lit(Ident::Str(int(&args[0])?.to_string().into())),
+        Prim::StringAppend => {
+            let joined = [string(&args[0])?, string(&args[1])?].concat();
+            lit(Ident::Str(Rc::from(joined)))
+        },
+        Prim::StringLength => {
+            lit(Ident::Nat(string(&args[0])?.chars().count() as u64))
+        },
+        Prim::StringEq => {
+            Some(boolean(string(&args[0])? == string(&args[1])?, ctx_size))
+        },
+    }
+}
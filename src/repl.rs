@@ -0,0 +1,366 @@
+use std::io::{self, BufRead, Write};
+use std::mem;
+
+use ast;
+use indent_parser::ProgramParser;
+use modules::Modules;
+use patterns::as_split;
+use pretty::{Pretty, param_names};
+
+use super::{Def, Expr, Globals, Scope};
+use super::{check_items, evaluate, evaluate_expr, find_global, get_index};
+use super::{infer_expr, print_global};
+
+// where errors in what is typed into the repl are reported
+const REPL_FILE: &str = "<repl>";
+
+const HELP: &str = "\
+Definitions and imports are checked as soon as they are entered, except for
+annotations, which wait for the definition after them. Lines ending in \\
+continue onto the next one, as they do in files.
+  :type expr       the type of expr
+  :eval expr       the normal form of expr
+  :print name      the type of a global, and how it computes
+  :overloads name  every global with the short name name
+  :reload          check the files again, forgetting everything entered
+  :quit            stop";
+
+// reads definitions and commands from stdin until it ends, checking them
+// in the context of globals, which has files loaded into it by modules
+pub fn repl(
+    parser: &ProgramParser,
+    modules: &mut Modules,
+    files: &[String],
+    globals: &mut Globals,
+) {
+    let mut session = Session {
+        parser,
+        modules,
+        files,
+        globals,
+        pending: None,
+    };
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut entry = String::new();
+    loop {
+        let waiting = !entry.is_empty() || session.pending.is_some();
+        print!("{}", if waiting { "| " } else { "> " });
+        io::stdout().flush().ok();
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => break,
+        };
+        entry.push_str(&line);
+        if line.ends_with('\\') {
+            // kept for the parser, which joins the lines just like in files
+            entry.push('\n');
+            continue;
+        }
+        let entry = mem::take(&mut entry);
+        if !session.enter(&entry) {
+            return;
+        }
+    }
+    println!();
+}
+
+struct Session<'a> {
+    parser: &'a ProgramParser,
+    modules: &'a mut Modules,
+    files: &'a [String],
+    globals: &'a mut Globals,
+    // an annotation that was entered on its own, waiting for its definition
+    pending: Option<String>,
+}
+
+impl<'a> Session<'a> {
+    // handles one entry, returning false if it asked to stop
+    fn enter(&mut self, entry: &str) -> bool {
+        if !entry.trim_start().starts_with(':') {
+            if !entry.trim().is_empty() || self.pending.is_some() {
+                self.define(entry);
+            }
+            return true;
+        }
+        let command = entry.replace("\\\n", "");
+        let command = command.trim();
+        let (name, arg) = match command.find(char::is_whitespace) {
+            Some(i) => (&command[..i], command[i..].trim()),
+            None => (command, ""),
+        };
+        match name {
+            ":type" | ":t" => self.type_of(arg),
+            ":eval" | ":e" => {
+                let is_name = arg.chars()
+                    .all(|c| c.is_alphanumeric() || "_'".contains(c));
+                let result = if is_name {
+                    evaluate(self.globals, arg)
+                } else {
+                    evaluate_expr(self.globals, arg)
+                };
+                match result {
+                    Ok(value) => println!("{} = {}", arg, value),
+                    Err(e) => eprintln!("{}", e),
+                }
+            },
+            ":print" | ":p" => {
+                match find_global(self.globals, REPL_FILE, arg) {
+                    Ok(i) => {
+                        print_global(self.globals, i);
+                        print_definition(self.globals, i);
+                    },
+                    Err(e) => eprintln!("{}", e),
+                }
+            },
+            ":overloads" | ":o" => {
+                let s = get_index(&self.globals.short_names, &arg.into());
+                match s {
+                    Some(s) => for &i in &self.globals.overloads[s] {
+                        print_global(self.globals, i);
+                    },
+                    None => eprintln!("nothing is called {}", arg),
+                }
+            },
+            ":reload" | ":r" => self.reload(),
+            ":quit" | ":q" => return false,
+            ":help" | ":h" | ":?" => println!("{}", HELP),
+            _ => eprintln!("unknown command {}, try :help", name),
+        }
+        true
+    }
+
+    fn type_of(&self, source: &str) {
+        let result = self.parser.parse_expr(REPL_FILE, source)
+            .and_then(|expr| {
+                infer_expr(self.globals, REPL_FILE, &expr, &Scope::default())
+            });
+        match result {
+            Ok((_, ty)) => {
+                println!("{}: {}", source, Pretty::new(self.globals, &[], &ty));
+            },
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    // checks the items in entry, and keeps them only if they all check
+    fn define(&mut self, entry: &str) {
+        let text = match self.pending.take() {
+            Some(pending) => format!("{}\n{}", pending, entry),
+            None => entry.into(),
+        };
+        let items = match self.parser.parse(REPL_FILE, &text) {
+            Ok(items) => items,
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            },
+        };
+        if let [item] = &items[..] {
+            if is_lone_annotation(item) {
+                self.pending = Some(text);
+                return;
+            }
+        }
+
+        for import in items.iter().filter_map(|item| item.import.as_ref()) {
+            let errors = self.modules.load_import(
+                self.parser, self.globals, REPL_FILE, import, false);
+            if let Some(e) = errors.first() {
+                eprintln!("{}", e);
+                return;
+            }
+        }
+        // what is entered is in the module with no name, like the builtins
+        self.globals.enter_module("");
+        let len = self.globals.names.len();
+        let data_len = self.globals.data_types.len();
+        let (checked, errors) = check_items(self.globals, &items, false);
+        if let Some(e) = errors.first() {
+            eprintln!("{}", e);
+            self.globals.truncate(len, data_len);
+            return;
+        }
+        if self.globals.names.len() > len {
+            self.modules.stop_caching();
+        }
+        for i in checked {
+            print_global(self.globals, i);
+        }
+    }
+
+    fn reload(&mut self) {
+        *self.globals = Globals::with_options(self.globals.options);
+        self.modules.forget();
+        self.pending = None;
+        let mut count = 0;
+        for file in self.files {
+            for e in self.modules.load(self.parser, self.globals, file, true) {
+                eprintln!("{}\n", e);
+                count += 1;
+            }
+        }
+        if count > 0 {
+            eprintln!("{} error(s)", count);
+        }
+    }
+}
+
+// an annotation that will be defined by the next entry
+fn is_lone_annotation(item: &ast::Item) -> bool {
+    item.definition.is_none()
+        && item.data.is_none()
+        && item.mutual.is_none()
+        && item.import.is_none()
+        && item.annotation.as_ref().is_some_and(|a| !a.is_post)
+}
+
+// what the parameters of a definition have been matched against so far,
+// where each pattern is an index into a list of them, and what to call it
+// while it is still a variable
+struct Pattern {
+    constructor: Option<usize>,
+    fields: Vec<usize>,
+    name: String,
+}
+
+// prints how the global at index i computes, as the clauses that it reduces
+// by, or else what kind of global it is
+fn print_definition(globals: &Globals, i: usize) {
+    let name = &globals.names[i];
+    let (params, def) = match &globals.defs[i].def {
+        Some((params, def)) => (*params, def),
+        None => {
+            let data = globals.data_types.iter()
+                .find(|data| data.index == i || data.constructors.contains(&i));
+            match data {
+                Some(data) if data.index == i => {
+                    let constructors: Vec<_> = data.constructors.iter()
+                        .map(|&c| &globals.names[c][..])
+                        .collect();
+                    println!("{} is a data type with constructors {}",
+                        name, constructors.join(", "));
+                },
+                Some(data) => println!("{} is a constructor of {}",
+                    name, globals.names[data.index]),
+                None => println!("{} is a postulate", name),
+            }
+            return;
+        },
+    };
+    if let Def::Prim(_) = def {
+        println!("{} is computed natively", name);
+        return;
+    }
+    let body = match def {
+        Def::Body(body) => body,
+        _ => return,
+    };
+    let ty = &globals.defs[i].ty;
+    let patterns = param_names(globals, &[], &ty.arrow_params[..params])
+        .into_iter()
+        .map(|name| Pattern { constructor: None, fields: Vec::new(), name })
+        .collect();
+    let mut printer = ClausePrinter { globals, name, params, patterns };
+    let vars: Vec<_> = (0..params).collect();
+    printer.print(vars.clone(), &vars, params, body);
+}
+
+// prints the clauses of the global called name, whose first params patterns
+// are its parameters
+struct ClausePrinter<'a> {
+    globals: &'a Globals,
+    name: &'a str,
+    params: usize,
+    patterns: Vec<Pattern>,
+}
+
+impl<'a> ClausePrinter<'a> {
+    // args are the patterns that the variables tree can match on are bound
+    // by, which are at the levels vars in a context of ctx_size
+    fn print(
+        &mut self,
+        args: Vec<usize>,
+        vars: &[usize],
+        ctx_size: usize,
+        tree: &Expr,
+    ) {
+        let globals = self.globals;
+        let split = match as_split(globals, tree, vars, ctx_size) {
+            Some(split) => split,
+            None => {
+                let mut names = vec!["_".to_string(); ctx_size];
+                for (&a, &l) in args.iter().zip(vars) {
+                    names[l] = self.patterns[a].name.clone();
+                }
+                let arg_names: Vec<_> = args.iter()
+                    .map(|&a| self.patterns[a].name.clone())
+                    .collect();
+                let mut clause = self.name.to_string();
+                for p in 0..self.params {
+                    clause.push(' ');
+                    write_pattern(globals, &self.patterns, &args, &arg_names,
+                        p, &mut clause);
+                }
+                println!("{} = {}", clause, Pretty::new(globals, &names, tree));
+                return;
+            },
+        };
+        let major = split.major;
+        for case in split.cases {
+            let fields = case.fields.len();
+            let first = self.patterns.len();
+            let taken: Vec<_> = args.iter()
+                .filter(|&&a| a != args[major])
+                .map(|&a| self.patterns[a].name.clone())
+                .collect();
+            for name in param_names(globals, &taken, &case.fields) {
+                self.patterns.push(Pattern {
+                    constructor: None,
+                    fields: Vec::new(),
+                    name,
+                });
+            }
+            let matched = args[major];
+            self.patterns[matched].constructor = Some(case.constructor);
+            self.patterns[matched].fields = (first..first + fields).collect();
+            let mut inner = args.clone();
+            inner.splice(major..major + 1, first..first + fields);
+            self.print(inner, &case.vars, case.ctx_size, &case.body);
+            self.patterns[matched].constructor = None;
+            self.patterns[matched].fields.clear();
+            self.patterns.truncate(first);
+        }
+    }
+}
+
+// variables are named after the argument of the case that they end up as
+fn write_pattern(
+    globals: &Globals,
+    patterns: &[Pattern],
+    args: &[usize],
+    names: &[String],
+    p: usize,
+    out: &mut String,
+) {
+    let pattern = &patterns[p];
+    let c = match pattern.constructor {
+        Some(c) => c,
+        None => {
+            let arg = args.iter().position(|&a| a == p).unwrap();
+            out.push_str(&names[arg]);
+            return;
+        },
+    };
+    if pattern.fields.is_empty() {
+        out.push_str(&globals.names[c]);
+        return;
+    }
+    out.push('(');
+    out.push_str(&globals.names[c]);
+    for &field in &pattern.fields {
+        out.push(' ');
+        write_pattern(globals, patterns, args, names, field, out);
+    }
+    out.push(')');
+}
@@ -0,0 +1,264 @@
+use std::io::{self, BufRead, Write};
+
+use error::{LoferError, Location};
+use pretty::Pretty;
+use prims;
+
+use super::{Expr, Globals, Ident};
+use super::{eval, get_index, subst};
+
+// where errors that happen while running a program are reported
+const RUN_FILE: &str = "<run>";
+
+// the postulates that the runtime gives meaning to, by name
+// IO, return, bind and abort are as in weak.ls:
+//   return: (A: Type) -> A -> IO A
+//   bind: (A: Type) -> (B: Type) -> (A -> IO B) -> IO A -> IO B
+//   abort: (A: Type) -> IO A
+// and the rest work with the primitive Nat or any data type shaped like it,
+// and a Unit with one constructor (or `(A: Type) -> A -> A`):
+//   print_nat: Nat -> IO Unit
+//   read_nat: IO Nat
+// as well as the primitive String type:
+//   print_string: String -> IO Unit
+//   read_line: IO String
+enum Action {
+    Return(Expr),
+    Bind(Expr, Expr),
+    Abort,
+    PrintNat(Expr),
+    ReadNat,
+    PrintString(Expr),
+    ReadLine,
+}
+
+fn error(message: String) -> LoferError {
+    LoferError::Runtime {
+        loc: Location { file: RUN_FILE.into(), line: 1, column: 1 },
+        message,
+    }
+}
+
+// runs the IO action defined by the global called name, performing the
+// effects that it asks for in order
+pub fn run(globals: &Globals, name: &str) -> Result<(), LoferError> {
+    let index = match get_index(&globals.names, &name.to_string()) {
+        Some(index) => index,
+        None => return Err(LoferError::UnknownIdentifier {
+            loc: Location { file: RUN_FILE.into(), line: 1, column: 1 },
+            name: name.into(),
+        }),
+    };
+    let mut current = Expr::from_head(Ident::Global(index));
+    // what to do with the result of the current action, innermost last
+    let mut continuations = Vec::new();
+    loop {
+        eval(&globals.defs, &mut current, 0);
+        let result = match action(globals, &current)? {
+            Action::Return(value) => value,
+            Action::Bind(f, x) => {
+                continuations.push(f);
+                current = x;
+                continue;
+            },
+            Action::Abort => return Err(error(format!("{} aborted", name))),
+            Action::PrintNat(n) => {
+                println!("{}", decode_nat(globals, &n)?);
+                unit(globals, "print_nat")?
+            },
+            Action::ReadNat => {
+                let line = read_line()?;
+                let line = line.trim();
+                match line.parse() {
+                    Ok(n) => encode_nat(globals, n)?,
+                    Err(_) => return Err(error(format!(
+                        "expected a number, but read {:?}", line))),
+                }
+            },
+            Action::PrintString(s) => {
+                println!("{}", decode_string(globals, &s)?);
+                unit(globals, "print_string")?
+            },
+            Action::ReadLine => {
+                let line = read_line()?;
+                let line = line.trim_end_matches(&['\r', '\n'][..]);
+                Expr::from_head(Ident::Str(line.into()))
+            },
+        };
+        current = match continuations.pop() {
+            Some(f) => apply(f, result)?,
+            None => return Ok(()),
+        };
+    }
+}
+
+fn read_line() -> Result<String, LoferError> {
+    let mut line = String::new();
+    io::stdout().flush().ok();
+    if let Err(e) = io::stdin().lock().read_line(&mut line) {
+        return Err(error(format!("could not read a line: {}", e)));
+    }
+    Ok(line)
+}
+
+fn apply(f: Expr, arg: Expr) -> Result<Expr, LoferError> {
+    // a placeholder head, which insert replaces with f
+    let mut result = Expr::universe(0);
+    result.tail.push(arg);
+    if !result.insert(f, 0) {
+        return Err(error("bound an action to something that isn't a function"
+            .into()));
+    }
+    Ok(result)
+}
+
+fn action(globals: &Globals, io: &Expr) -> Result<Action, LoferError> {
+    let name = match io.head {
+        Ident::Global(i) if io.lambda_params == 0
+            && io.arrow_params.is_empty() =>
+        {
+            &globals.names[i][..]
+        },
+        _ => "",
+    };
+    let args = &io.tail;
+    Ok(match (name, args.len()) {
+        ("return", 2) => Action::Return(args[1].clone()),
+        ("bind", 4) => Action::Bind(args[2].clone(), args[3].clone()),
+        ("abort", 1) => Action::Abort,
+        ("print_nat", 1) => Action::PrintNat(args[0].clone()),
+        ("read_nat", 0) => Action::ReadNat,
+        ("print_string", 1) => Action::PrintString(args[0].clone()),
+        ("read_line", 0) => Action::ReadLine,
+        _ => return Err(error(format!(
+            "got stuck running an IO action that isn't built from the \
+            runtime's primitives:\n  {}",
+            Pretty::new(globals, &[], io)))),
+    })
+}
+
+// the constructor taking no fields and the constructor taking one field of a
+// data type shaped like Nat
+fn nat_constructors(globals: &Globals, ty: &Expr)
+    -> Result<(usize, usize), LoferError>
+{
+    let data = match ty.head {
+        Ident::Global(d) => {
+            globals.data_types.iter().find(|data| data.index == d)
+        },
+        _ => None,
+    };
+    let fields = |c: usize| globals.defs[c].ty.arrow_params.len();
+    if let Some(data) = data {
+        if let [a, b] = data.constructors[..] {
+            if data.params == 0 && data.indices == 0 {
+                match (fields(a), fields(b)) {
+                    (0, 1) => return Ok((a, b)),
+                    (1, 0) => return Ok((b, a)),
+                    _ => (),
+                }
+            }
+        }
+    }
+    Err(error(format!(
+        "{} is not a data type with a constructor like zero and one like suc",
+        Pretty::new(globals, &[], ty))))
+}
+
+// the type that a primitive's IO result or last parameter has
+fn prim_type(globals: &Globals, name: &str, result: bool)
+    -> Result<Expr, LoferError>
+{
+    let index = get_index(&globals.names, &name.to_string())
+        .ok_or_else(|| error(format!("{} was never declared", name)))?;
+    let ty = &globals.defs[index].ty;
+    let params = ty.arrow_params.len();
+    let (ex, ctx_size) = if result {
+        (ty.tail.last(), params)
+    } else {
+        (ty.arrow_params.last().map(|p| &p.ty), params.saturating_sub(1))
+    };
+    let ex = ex.ok_or_else(|| error(format!("{} has the wrong type", name)))?;
+    // the primitive's own parameters can't be mentioned, so this just moves
+    // the binders inside ex back to the start of the context
+    let unused = vec![Expr::universe(0); ctx_size];
+    let mut ex = subst(ex, 0, 0, &unused, 0);
+    eval(&globals.defs, &mut ex, 0);
+    Ok(ex)
+}
+
+fn decode_nat(globals: &Globals, n: &Expr) -> Result<u64, LoferError> {
+    if let Ident::Nat(k) = n.head {
+        return Ok(k);
+    }
+    let ty = prim_type(globals, "print_nat", false)?;
+    let (zero, suc) = nat_constructors(globals, &ty)?;
+    let mut result = 0;
+    let mut n = n;
+    loop {
+        if n.head == Ident::Global(zero) && n.tail.is_empty() {
+            return Ok(result);
+        }
+        if n.head == Ident::Global(suc) && n.tail.len() == 1 {
+            result += 1;
+            n = &n.tail[0];
+            continue;
+        }
+        return Err(error(format!(
+            "could not print {}, since it isn't a number",
+            Pretty::new(globals, &[], n))));
+    }
+}
+
+fn decode_string(globals: &Globals, s: &Expr) -> Result<String, LoferError> {
+    match s.head {
+        Ident::Str(ref text) if s.tail.is_empty() => Ok(text.to_string()),
+        _ => Err(error(format!(
+            "could not print {}, since it isn't a string",
+            Pretty::new(globals, &[], s)))),
+    }
+}
+
+fn encode_nat(globals: &Globals, n: u64) -> Result<Expr, LoferError> {
+    let ty = prim_type(globals, "read_nat", true)?;
+    if ty == Expr::from_head(Ident::Global(prims::NAT)) {
+        return Ok(Expr::from_head(Ident::Nat(n)));
+    }
+    let (zero, suc) = nat_constructors(globals, &ty)?;
+    let mut result = Expr::from_head(Ident::Global(zero));
+    for _ in 0..n {
+        let mut next = Expr::from_head(Ident::Global(suc));
+        next.tail.push(result);
+        result = next;
+    }
+    Ok(result)
+}
+
+// the only value of the type that a primitive returns
+fn unit(globals: &Globals, name: &str) -> Result<Expr, LoferError> {
+    let ty = prim_type(globals, name, true)?;
+    if let Ident::Global(d) = ty.head {
+        let data = globals.data_types.iter().find(|data| data.index == d);
+        if let Some(data) = data {
+            if let [c] = data.constructors[..] {
+                if globals.defs[c].ty.arrow_params.is_empty() {
+                    return Ok(Expr::from_head(Ident::Global(c)));
+                }
+            }
+        }
+    }
+    // (A: Type) -> A -> A, whose value is \A x -> x
+    let church = ty.arrow_params.len() == 2
+        && ty.arrow_params[0].ty.universe_level().is_some()
+        && ty.arrow_params[1].ty == Expr::from_head(Ident::Local(0))
+        && ty.head == Ident::Local(0)
+        && ty.tail.is_empty();
+    if church {
+        let mut value = Expr::from_head(Ident::Local(1));
+        value.lambda_params = 2;
+        value.lambda_names = vec![None; 2];
+        return Ok(value);
+    }
+    Err(error(format!(
+        "{} doesn't return a unit type that the runtime knows", name)))
+}
@@ -0,0 +1,377 @@
+use std::ptr;
+
+use ast;
+use error::{LoferError, Location};
+use patterns;
+use pretty::{self, Pretty};
+
+use super::{Def, Expr, Globals, Ident};
+
+// how an argument of a call compares to a parameter of the caller
+#[derive(Clone, Copy)]
+enum Size {
+    Smaller(usize),
+    Equal(usize),
+    Unknown,
+}
+
+// a call from one member of a group to another, found somewhere in the
+// caller's definition
+struct Call {
+    caller: usize,
+    callee: usize,
+    args: Vec<Size>,
+    expr: Expr,
+    ctx_size: usize,
+    // the names of the caller's variables where the call was found
+    names: Vec<String>,
+}
+
+// a variable that was split into a constructor, so that applying that
+// constructor to the same fields again gives back the same value
+#[derive(Clone)]
+struct Split {
+    constructor: usize,
+    params: usize,
+    fields: Vec<usize>,
+    origin: Option<(usize, bool)>,
+}
+
+// which parameter each variable in the context is part of, and whether it
+// is strictly smaller than it, along with what to call it in errors
+#[derive(Clone)]
+struct Origins {
+    vars: Vec<Option<(usize, bool)>>,
+    splits: Vec<Split>,
+    names: Vec<String>,
+}
+
+// an item of a group, its index in globals, and its definition, if it had one
+// that checked
+pub type Member<'a> = (&'a ast::Item, usize, Option<(usize, Def)>);
+
+// the same, for members and associated items alike
+type Checked<'a> = (&'a ast::Item, usize, Option<&'a (usize, Def)>);
+
+// checks that a group of definitions that can call each other terminate,
+// using the size-change principle: every way of calling back into the same
+// definition forever has to keep making some parameter smaller
+// postulates and partial definitions are trusted instead
+// the associated items of the group are part of it too, since they can call
+// the items they belong to, and are given by their items and indices
+pub fn check(
+    globals: &Globals,
+    defs: &[Member],
+    helpers: &[(&ast::Item, usize)],
+) -> Result<(), LoferError> {
+    // associated items without annotations are always checked
+    let checked = |item: &ast::Item| {
+        item.annotation.as_ref()
+            .is_none_or(|a| !a.is_post && !a.is_partial)
+    };
+    let defs: Vec<Checked> = defs.iter()
+        .map(|(item, index, def)| (*item, *index, def.as_ref()))
+        .chain(helpers.iter().map(|&(item, index)| {
+            (item, index, globals.defs[index].def.as_ref())
+        }))
+        .collect();
+    let mut members = Vec::new();
+    let mut param_nums = Vec::new();
+    for (item, index, def) in &defs {
+        if let Some((param_num, _)) = def {
+            if checked(item) {
+                members.push(*index);
+                param_nums.push(*param_num);
+            }
+        }
+    }
+
+    let mut calls = Vec::new();
+    let mut m = 0;
+    for (item, index, def) in &defs {
+        if let Some((param_num, def)) = def {
+            if checked(item) {
+                let params = &globals.defs[*index].ty.arrow_params;
+                let origins = Origins {
+                    vars: (0..*param_num).map(|k| Some((k, false))).collect(),
+                    splits: Vec::new(),
+                    names: pretty::param_names(
+                        globals, &[], &params[..*param_num]),
+                };
+                collect_def(globals, &members, m, def, origins, &mut calls);
+                m += 1;
+            }
+        }
+    }
+
+    let bad = match looping_call(&param_nums, &calls) {
+        Some(call) => &calls[call],
+        None => return Ok(()),
+    };
+    let (item, _, _) = defs.iter()
+        .find(|(_, index, _)| *index == members[bad.caller])
+        .unwrap();
+    let definition = item.definition.as_ref().unwrap();
+    let mut names = pretty::local_names(globals, &bad.names, bad.ctx_size);
+    let mut call = bad.expr.clone();
+    if let Some((helper, captured)) = as_helper(&defs, &call.head) {
+        // Pretty writes globals by their full names, so the helper's short
+        // name is given to it as if it were a local
+        call.head = Ident::Local(names.len());
+        names.push(helper.to_string());
+        let params = &globals.defs[members[bad.callee]].ty.arrow_params;
+        call.tail = call.tail.drain(..)
+            .enumerate()
+            .skip(captured)
+            .filter(|&(k, _)| params.get(k).is_none_or(|p| !p.implicit))
+            .map(|(_, arg)| arg)
+            .collect();
+    }
+    Err(LoferError::Termination {
+        loc: Location::at(&item.file, definition.span),
+        name: globals.names[members[bad.caller]].clone(),
+        call: Pretty::new(globals, &names, &call).to_string(),
+    })
+}
+
+// the name that an associated item is called by in the source, and how many
+// parameters of the items it is nested in it was lifted to take, which are
+// passed to it without being written
+fn as_helper<'a>(defs: &[Checked<'a>], head: &Ident)
+    -> Option<(&'a str, usize)>
+{
+    let (item, _, _) = defs.iter()
+        .find(|(_, index, _)| Ident::Global(*index) == *head)?;
+    let (_, _, parent) = defs.iter().find(|(parent, _, _)| {
+        parent.associated.iter().any(|each| ptr::eq(each, *item))
+    })?;
+    let captured = parent.map_or(0, |(param_num, _)| *param_num);
+    let name = match (&item.definition, &item.annotation) {
+        (Some(definition), _) => &definition.fname,
+        (None, Some(annotation)) => &annotation.name,
+        (None, None) => return None,
+    };
+    Some((name, captured))
+}
+
+fn collect_def(
+    globals: &Globals,
+    members: &[usize],
+    caller: usize,
+    def: &Def,
+    origins: Origins,
+    calls: &mut Vec<Call>,
+) {
+    if let Def::Body(body) = def {
+        let vars: Vec<_> = (0..origins.vars.len()).collect();
+        collect_tree(globals, members, caller, body, &vars, origins, calls);
+    }
+}
+
+// the fields of each case of a split are smaller than the variable split on,
+// and the variables after it are the same as they were, where vars are the
+// levels of the variables that the tree can still match on
+fn collect_tree(
+    globals: &Globals,
+    members: &[usize],
+    caller: usize,
+    tree: &Expr,
+    vars: &[usize],
+    origins: Origins,
+    calls: &mut Vec<Call>,
+) {
+    let ctx_size = origins.vars.len();
+    let split = match patterns::as_split(globals, tree, vars, ctx_size) {
+        Some(split) => split,
+        None => {
+            collect_expr(members, caller, tree, &origins, ctx_size, calls);
+            return;
+        },
+    };
+    let major = vars[split.major];
+    let origin = origins.vars[major];
+    let later = &vars[split.major + 1..];
+    for case in split.cases {
+        let nf = case.fields.len();
+        let reverted = ctx_size + nf + case.hypotheses;
+        // the variables after the one split on are taken again
+        let relevel = |l: usize| match later.iter().position(|&v| v == l) {
+            Some(k) => reverted + k,
+            None => l,
+        };
+        let mut inner_vars = origins.vars.clone();
+        inner_vars.extend((0..nf).map(|_| origin.map(|(k, _)| (k, true))));
+        inner_vars.extend((0..case.hypotheses).map(|_| None));
+        inner_vars.extend(later.iter().map(|&l| origins.vars[l]));
+        let mut splits: Vec<_> = origins.splits.iter()
+            .filter(|split| !split.fields.contains(&major))
+            .map(|split| Split {
+                fields: split.fields.iter().map(|&l| relevel(l)).collect(),
+                ..split.clone()
+            })
+            .collect();
+        splits.push(Split {
+            constructor: case.constructor,
+            params: split.params,
+            fields: (ctx_size..ctx_size + nf).collect(),
+            origin,
+        });
+        let taken: Vec<_> = vars.iter()
+            .filter(|&&l| l != major)
+            .map(|&l| origins.names[l].clone())
+            .collect();
+        let mut names = origins.names.clone();
+        names.extend(pretty::param_names(globals, &taken, &case.fields));
+        names.extend((0..case.hypotheses).map(|_| "_".to_string()));
+        names.extend(later.iter().map(|&l| origins.names[l].clone()));
+        let inner = Origins { vars: inner_vars, splits, names };
+        collect_tree(globals, members, caller, &case.body, &case.vars, inner,
+            calls);
+    }
+}
+
+fn collect_expr(
+    members: &[usize],
+    caller: usize,
+    expr: &Expr,
+    origins: &Origins,
+    ctx_size: usize,
+    calls: &mut Vec<Call>,
+) {
+    let mut inner = ctx_size + expr.lambda_params;
+    for param in &expr.arrow_params {
+        collect_expr(members, caller, &param.ty, origins, inner, calls);
+        inner += 1;
+    }
+    for ex in &expr.tail {
+        collect_expr(members, caller, ex, origins, inner, calls);
+    }
+    if let Ident::Global(g) = expr.head {
+        if let Some(callee) = members.iter().position(|&m| m == g) {
+            let args = expr.tail.iter().map(|ex| size(ex, origins)).collect();
+            let mut call = expr.clone();
+            call.lambda_params = 0;
+            call.lambda_names.clear();
+            call.arrow_params.clear();
+            calls.push(Call {
+                caller,
+                callee,
+                args,
+                expr: call,
+                ctx_size: inner,
+                names: origins.names.clone(),
+            });
+        }
+    }
+}
+
+fn size(arg: &Expr, origins: &Origins) -> Size {
+    if arg.lambda_params > 0 || !arg.arrow_params.is_empty() {
+        return Size::Unknown;
+    }
+    let origin = match arg.head {
+        Ident::Local(l) if l < origins.vars.len() => origins.vars[l],
+        Ident::Global(c) => {
+            let is_field = |ex: &Expr, l: usize| {
+                *ex == Expr::from_head(Ident::Local(l))
+            };
+            let split = origins.splits.iter().find(|split| {
+                split.constructor == c
+                    && arg.tail.len() == split.params + split.fields.len()
+                    && arg.tail[split.params..].iter()
+                        .zip(&split.fields)
+                        .all(|(ex, &l)| is_field(ex, l))
+            });
+            match split {
+                Some(Split { origin: Some((k, true)), .. }) => {
+                    return Size::Smaller(*k);
+                },
+                Some(Split { origin: Some((k, false)), .. }) => {
+                    return Size::Equal(*k);
+                },
+                _ => None,
+            }
+        },
+        _ => None,
+    };
+    match origin {
+        // applying a smaller function still gives something smaller
+        Some((k, true)) => Size::Smaller(k),
+        Some((k, false)) if arg.tail.is_empty() => Size::Equal(k),
+        _ => Size::Unknown,
+    }
+}
+
+// how the parameters of the callee of a sequence of calls compare to the
+// caller's, indexed by caller parameter and then callee parameter, where
+// Some(true) means strictly smaller
+#[derive(Clone)]
+struct Graph {
+    caller: usize,
+    callee: usize,
+    sizes: Vec<Vec<Option<bool>>>,
+    // the call the sequence started with, for error messages
+    first: usize,
+}
+
+// the sizes after a sequence of calls followed by another, where the second
+// sequence ends in a definition with callee_params parameters
+fn compose(a: &Graph, b: &Graph, callee_params: usize) -> Graph {
+    let sizes = a.sizes.iter().map(|row| {
+        (0..callee_params).map(|l| {
+            let mut result = None;
+            for (j, size) in row.iter().enumerate() {
+                if let (Some(x), Some(y)) = (*size, b.sizes[j][l]) {
+                    result = Some(result == Some(true) || x || y);
+                }
+            }
+            result
+        }).collect()
+    }).collect();
+    Graph { caller: a.caller, callee: b.callee, sizes, first: a.first }
+}
+
+// finds a call that starts a sequence of calls that could loop forever,
+// if there is one
+fn looping_call(param_nums: &[usize], calls: &[Call]) -> Option<usize> {
+    let mut graphs: Vec<Graph> = calls.iter().enumerate().map(|(i, call)| {
+        let caller_params = param_nums[call.caller];
+        let callee_params = param_nums[call.callee];
+        let mut sizes = vec![vec![None; callee_params]; caller_params];
+        for (j, size) in call.args.iter().take(callee_params).enumerate() {
+            match *size {
+                Size::Smaller(k) => sizes[k][j] = Some(true),
+                Size::Equal(k) => sizes[k][j] = Some(false),
+                Size::Unknown => (),
+            }
+        }
+        Graph { caller: call.caller, callee: call.callee, sizes, first: i }
+    }).collect();
+    let base = graphs.clone();
+
+    // every sequence of calls, up to what it does to the sizes
+    let mut i = 0;
+    while i < graphs.len() {
+        for next in &base {
+            if graphs[i].callee != next.caller {
+                continue;
+            }
+            let composed = compose(&graphs[i], next, param_nums[next.callee]);
+            let seen = graphs.iter().any(|g| {
+                g.caller == composed.caller
+                    && g.callee == composed.callee
+                    && g.sizes == composed.sizes
+            });
+            if !seen {
+                graphs.push(composed);
+            }
+        }
+        i += 1;
+    }
+
+    graphs.iter().find(|g| {
+        g.caller == g.callee
+            && compose(g, g, param_nums[g.callee]).sizes == g.sizes
+            && !(0..g.sizes.len()).any(|k| g.sizes[k][k] == Some(true))
+    }).map(|g| g.first)
+}
@@ -0,0 +1,458 @@
+use std::mem;
+use std::rc::Rc;
+
+use ast;
+use prims;
+
+use super::{CheckError, CheckResult, ErrorKind, Expr, Globals, Ident};
+use super::{deepen, eval};
+
+// the implicit arguments inserted while checking a single item, each of which
+// may be solved by unification as checking continues
+// metas are first order: a solution lives in the context the meta was created
+// in, and applying an unsolved meta to arguments never solves it
+#[derive(Clone, Default)]
+pub struct Metas {
+    solutions: Vec<Option<Expr>>,
+    ctx_sizes: Vec<usize>,
+    // for error messages, where the meta was inserted and what it was an
+    // argument to
+    spans: Vec<ast::Span>,
+    heads: Vec<Ident>,
+    holes: Vec<Hole>,
+    // the overloads chosen so far, which are kept when backtracking since
+    // they only depend on their keys
+    choices: Vec<(ChoiceKey, CheckResult<usize>)>,
+}
+
+// an overloaded application, along with everything that choosing which
+// overload it is depends on
+#[derive(Clone, PartialEq)]
+pub struct ChoiceKey {
+    pub span: ast::Span,
+    pub overload: usize,
+    pub args: Vec<Expr>,
+    pub expected: Option<Expr>,
+    pub context: Vec<Expr>,
+}
+
+// a hole, with the type it was expected to have and the types of the locals
+// in scope, which may mention metas that were solved later
+#[derive(Clone)]
+pub struct Hole {
+    pub span: ast::Span,
+    pub name: Option<Rc<str>>,
+    pub ty: Expr,
+    pub context: Vec<Expr>,
+}
+
+impl Metas {
+    pub fn fresh(&mut self, ctx_size: usize, span: ast::Span, head: Ident)
+        -> Expr
+    {
+        let m = self.solutions.len();
+        self.solutions.push(None);
+        self.ctx_sizes.push(ctx_size);
+        self.spans.push(span);
+        self.heads.push(head);
+        Expr::from_head(Ident::Meta(m))
+    }
+
+    pub fn add_hole(&mut self, hole: Hole) {
+        self.holes.push(hole);
+    }
+
+    pub fn choice(&self, key: &ChoiceKey) -> Option<CheckResult<usize>> {
+        self.choices.iter()
+            .find(|(each, _)| each == key)
+            .map(|(_, choice)| choice.clone())
+    }
+
+    pub fn add_choice(
+        &mut self,
+        key: ChoiceKey,
+        choice: CheckResult<usize>,
+    ) {
+        self.choices.push((key, choice));
+    }
+
+    // used to backtrack when trying overloads
+    pub fn save(&self) -> Self {
+        Metas {
+            solutions: self.solutions.clone(),
+            ctx_sizes: self.ctx_sizes.clone(),
+            spans: self.spans.clone(),
+            heads: self.heads.clone(),
+            holes: self.holes.clone(),
+            choices: Vec::new(),
+        }
+    }
+    pub fn restore(&mut self, saved: &Self) {
+        let choices = mem::take(&mut self.choices);
+        *self = saved.save();
+        self.choices = choices;
+    }
+
+    // replaces every solved meta in an expression valid in a context of
+    // ctx_size variables, returning whether anything changed
+    pub fn zonk(&self, expr: &mut Expr, ctx_size: usize) -> bool {
+        let mut changed = false;
+        let mut inner = ctx_size + expr.lambda_params;
+        for param in &mut expr.arrow_params {
+            changed |= self.zonk(&mut param.ty, inner);
+            inner += 1;
+        }
+        for ex in &mut expr.tail {
+            changed |= self.zonk(ex, inner);
+        }
+        while let Ident::Meta(m) = expr.head {
+            if let Some(solution) = &self.solutions[m] {
+                // the solution's own binders need to move past the context
+                // that the meta occurred in
+                let extra = inner - self.ctx_sizes[m];
+                let mut solution = deepen(solution, self.ctx_sizes[m], extra);
+                self.zonk(&mut solution, inner);
+                if !expr.insert(solution, inner) {
+                    unreachable!("solutions always fit where their meta is");
+                }
+                changed = true;
+            } else {
+                break;
+            }
+        }
+        changed
+    }
+
+    // zonks an item once it has been checked, making sure nothing was left
+    // unsolved, and that it had no holes
+    pub fn finish(&self, expr: &mut Expr, ctx_size: usize)
+        -> CheckResult<()>
+    {
+        self.zonk(expr, ctx_size);
+        if !self.holes.is_empty() {
+            let holes = self.holes.iter()
+                .map(|hole| {
+                    let mut hole = hole.clone();
+                    self.zonk(&mut hole.ty, hole.context.len());
+                    for (i, ty) in hole.context.iter_mut().enumerate() {
+                        self.zonk(ty, i);
+                    }
+                    hole
+                })
+                .collect();
+            return Err(CheckError::new(
+                self.holes[0].span,
+                ErrorKind::Holes(holes),
+            ));
+        }
+        if let Some(m) = first_meta(expr) {
+            return Err(CheckError::new(
+                self.spans[m],
+                ErrorKind::UnsolvedMeta {
+                    head: Expr::from_head(self.heads[m].clone()),
+                    ctx_size: self.ctx_sizes[m],
+                },
+            ));
+        }
+        Ok(())
+    }
+
+    fn force(&self, globals: &Globals, expr: &Expr, ctx_size: usize)
+        -> Expr
+    {
+        let mut expr = expr.clone();
+        if self.zonk(&mut expr, ctx_size) {
+            eval(&globals.defs, &mut expr, ctx_size);
+        }
+        expr
+    }
+
+    fn bare_unsolved(&self, expr: &Expr) -> Option<usize> {
+        if expr.lambda_params > 0
+            || !expr.arrow_params.is_empty()
+            || !expr.tail.is_empty()
+        {
+            return None;
+        }
+        match expr.head {
+            Ident::Meta(m) if self.solutions[m].is_none() => Some(m),
+            _ => None,
+        }
+    }
+
+    fn solve(&mut self, m: usize, ctx_size: usize, value: &Expr) -> bool {
+        let target = self.ctx_sizes[m];
+        let mut shrunk = Vec::new();
+        let solution = self.strengthen(m, value, ctx_size, target, &mut shrunk);
+        if let Some(solution) = solution {
+            // whatever these are solved with later has to make sense here too
+            for k in shrunk {
+                self.ctx_sizes[k] = target;
+            }
+            self.solutions[m] = Some(solution);
+            true
+        } else {
+            false
+        }
+    }
+
+    // moves an expression valid in a context of `from` variables into the
+    // smaller context of `to` variables that meta m was created in,
+    // failing if it refers to anything in between, or to m itself
+    // the metas it mentions from bigger contexts are added to shrunk, to be
+    // moved into the smaller one if this succeeds
+    fn strengthen(
+        &self,
+        m: usize,
+        expr: &Expr,
+        from: usize,
+        to: usize,
+        shrunk: &mut Vec<usize>,
+    ) -> Option<Expr> {
+        let mut result = Expr::from_head(expr.head.clone());
+        result.lambda_params = expr.lambda_params;
+        result.lambda_names = expr.lambda_names.clone();
+        for param in &expr.arrow_params {
+            let mut param = param.clone();
+            param.ty = self.strengthen(m, &param.ty, from, to, shrunk)?;
+            result.arrow_params.push(param);
+        }
+        for ex in &expr.tail {
+            result.tail.push(self.strengthen(m, ex, from, to, shrunk)?);
+        }
+        match expr.head {
+            Ident::Local(i) if i >= from => {
+                // bound inside the expression itself
+                result.head = Ident::Local(i - from + to);
+            },
+            Ident::Local(i) if i >= to => return None,
+            Ident::Meta(k) if k == m => return None,
+            Ident::Meta(k) if self.ctx_sizes[k] > to => shrunk.push(k),
+            _ => (),
+        }
+        Some(result)
+    }
+}
+
+pub fn first_meta(expr: &Expr) -> Option<usize> {
+    if let Ident::Meta(m) = expr.head {
+        return Some(m);
+    }
+    expr.arrow_params.iter()
+        .filter_map(|param| first_meta(&param.ty))
+        .chain(expr.tail.iter().filter_map(first_meta))
+        .next()
+}
+
+// the first pair of sub-terms that didn't match when unifying, along with the
+// size of the context they are valid in
+#[derive(Clone)]
+pub struct Difference {
+    pub left: Expr,
+    pub right: Expr,
+    pub ctx_size: usize,
+}
+
+type UnifyResult = Result<(), Box<Difference>>;
+
+fn differ(left: Expr, right: Expr, ctx_size: usize) -> UnifyResult {
+    Err(Box::new(Difference { left, right, ctx_size }))
+}
+
+// the number of arguments a partially applied global still needs before it
+// will evaluate
+fn missing_args(globals: &Globals, expr: &Expr) -> usize {
+    if expr.lambda_params > 0 || !expr.arrow_params.is_empty() {
+        return 0;
+    }
+    match expr.head {
+        Ident::Global(i) => match &globals.defs[i].def {
+            Some((param_num, _)) if *param_num > expr.tail.len() =>
+                param_num - expr.tail.len(),
+            _ => 0,
+        },
+        _ => 0,
+    }
+}
+
+// applies a function valid in a context of ctx_size variables to the next n
+// variables, for comparing functions by their results
+// a lambda's own binders already are those variables
+fn apply_fresh(globals: &Globals, mut expr: Expr, ctx_size: usize, n: usize)
+    -> Expr
+{
+    if expr.lambda_params < n && !expr.arrow_params.is_empty() {
+        // not a function, so it won't be equal to one anyway
+        return expr;
+    }
+    let bound = expr.lambda_params.min(n);
+    expr.lambda_params -= bound;
+    expr.lambda_names.drain(0..bound);
+    for i in bound..n {
+        expr.tail.push(Expr::from_head(Ident::Local(ctx_size + i)));
+    }
+    eval(&globals.defs, &mut expr, ctx_size + n);
+    expr
+}
+
+// checks that two expressions valid in a context of ctx_size variables are
+// definitionally equal, solving metas wherever one side is just an unsolved
+// meta
+// functions are compared up to eta, so a lambda or a partially applied global
+// is the same as anything that gives the same results for the same arguments
+pub fn unify(
+    globals: &Globals,
+    metas: &mut Metas,
+    ctx_size: usize,
+    a: &Expr,
+    b: &Expr,
+) -> UnifyResult {
+    if a == b {
+        return Ok(());
+    }
+    let a = metas.force(globals, a, ctx_size);
+    let b = metas.force(globals, b, ctx_size);
+    if a == b {
+        return Ok(());
+    }
+    if let Some(m) = metas.bare_unsolved(&a) {
+        if metas.solve(m, ctx_size, &b) {
+            return Ok(());
+        }
+        return differ(a, b, ctx_size);
+    }
+    if let Some(m) = metas.bare_unsolved(&b) {
+        if metas.solve(m, ctx_size, &a) {
+            return Ok(());
+        }
+        return differ(a, b, ctx_size);
+    }
+
+    // there is only one universe with type in type, whatever it is called
+    if globals.options.type_in_type && a.is_universe() && b.is_universe() {
+        return Ok(());
+    }
+
+    let lambdas = a.lambda_params.max(b.lambda_params);
+    if lambdas > 0 {
+        let a = apply_fresh(globals, a, ctx_size, lambdas);
+        let b = apply_fresh(globals, b, ctx_size, lambdas);
+        return unify(globals, metas, ctx_size + lambdas, &a, &b);
+    }
+
+    // compare the parameters both sides have, then whatever is left over,
+    // in case a meta on one side stands for the rest of the other's arrows
+    let shared = a.arrow_params.len().min(b.arrow_params.len());
+    if shared > 0 {
+        let mut inner = ctx_size;
+        for (pa, pb) in a.arrow_params.iter().zip(&b.arrow_params) {
+            if pa.implicit != pb.implicit {
+                return differ(a.clone(), b.clone(), ctx_size);
+            }
+            unify(globals, metas, inner, &pa.ty, &pb.ty)?;
+            inner += 1;
+        }
+        let rest = |mut ex: Expr| {
+            ex.arrow_params.drain(0..shared);
+            ex
+        };
+        return unify(globals, metas, inner, &rest(a), &rest(b));
+    }
+
+    let missing = missing_args(globals, &a).max(missing_args(globals, &b));
+    if missing == 0 {
+        return unify_spines(globals, metas, ctx_size, &a, &b);
+    }
+    // the same partial application is usually the same function, but it
+    // could still be equal in other ways if not, so backtrack
+    if a.head == b.head {
+        let saved = metas.save();
+        if unify_spines(globals, metas, ctx_size, &a, &b).is_ok() {
+            return Ok(());
+        }
+        metas.restore(&saved);
+    }
+    let a = apply_fresh(globals, a, ctx_size, missing);
+    let b = apply_fresh(globals, b, ctx_size, missing);
+    unify(globals, metas, ctx_size + missing, &a, &b)
+}
+
+// checks that anything of type a also has type b, which is just equality
+// unless universes are cumulative, where U i is a subtype of U j for i <= j,
+// and so arrows ending in U i are subtypes of the same arrows ending in U j
+pub fn subtype(
+    globals: &Globals,
+    metas: &mut Metas,
+    ctx_size: usize,
+    a: &Expr,
+    b: &Expr,
+) -> UnifyResult {
+    if !globals.options.cumulative {
+        return unify(globals, metas, ctx_size, a, b);
+    }
+    let a = metas.force(globals, a, ctx_size);
+    let b = metas.force(globals, b, ctx_size);
+    if b.universe_level() == Some(prims::OMEGA) && a.is_universe() {
+        return Ok(());
+    }
+    let levels = (prims::universe_level(&a), prims::universe_level(&b));
+    if let (Some(la), Some(lb)) = levels {
+        if prims::level_leq(&la, &lb) {
+            return Ok(());
+        }
+    }
+
+    // parameters still have to be the same, only the results can grow
+    let shared = a.arrow_params.len().min(b.arrow_params.len());
+    if shared > 0 && a.lambda_params == 0 && b.lambda_params == 0 {
+        let mut inner = ctx_size;
+        for (pa, pb) in a.arrow_params.iter().zip(&b.arrow_params) {
+            if pa.implicit != pb.implicit {
+                return differ(a.clone(), b.clone(), ctx_size);
+            }
+            unify(globals, metas, inner, &pa.ty, &pb.ty)?;
+            inner += 1;
+        }
+        let rest = |mut ex: Expr| {
+            ex.arrow_params.drain(0..shared);
+            ex
+        };
+        return subtype(globals, metas, inner, &rest(a), &rest(b));
+    }
+    unify(globals, metas, ctx_size, &a, &b)
+}
+
+// compares a head applied to arguments with another, argument by argument
+fn unify_spines(
+    globals: &Globals,
+    metas: &mut Metas,
+    ctx_size: usize,
+    a: &Expr,
+    b: &Expr,
+) -> UnifyResult {
+    // U l is Un whenever l is n, and so on for the successors of levels
+    if a.head != b.head {
+        let universes = (prims::universe_level(a), prims::universe_level(b));
+        if let (Some(la), Some(lb)) = universes {
+            return unify(globals, metas, ctx_size, &la, &lb)
+                .or_else(|_| differ(a.clone(), b.clone(), ctx_size));
+        }
+        let suc = Ident::Global(prims::LEVEL_SUC);
+        let preds = (prims::level_pred(a), prims::level_pred(b));
+        if let (Some(pa), Some(pb)) = preds {
+            if a.head == suc || b.head == suc {
+                return unify(globals, metas, ctx_size, &pa, &pb);
+            }
+        }
+    }
+    if a.head != b.head
+        || a.arrow_params.len() != b.arrow_params.len()
+        || a.tail.len() != b.tail.len()
+    {
+        return differ(a.clone(), b.clone(), ctx_size);
+    }
+    for (ta, tb) in a.tail.iter().zip(&b.tail) {
+        unify(globals, metas, ctx_size, ta, tb)?;
+    }
+    Ok(())
+}